@@ -0,0 +1,613 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use log::LevelFilter;
+use preference_routing::config::{self, AppConfig};
+use preference_routing::graph::path::index_locations;
+use preference_routing::logging::{self, LogFormat};
+use preference_routing::{load_graph, parse_graph_file, server};
+use preference_routing::{Coordinate, Graph, Path, Preference, EDGE_COST_DIMENSION};
+
+pub const USAGE: &str = "Usage:
+  preference-routing [--config <file>] <command>
+      Read the config from the file instead of config.toml. route, validate
+      and convert only use a config given this way
+  preference-routing [serve] [graph]
+      Start the server on the graph and the graphs listed in config.toml
+  preference-routing route <graph> --alpha <a,b,c,d> <lat,lng> <lat,lng>... [--format json|geojson] [--output <file>]
+      Compute the route through the waypoints
+  preference-routing learn <graph> <route file> [--output <file>]
+      Find the preferences that explain the routes in the file, as written by route
+  preference-routing validate <graph>
+      Check the graph for inconsistencies
+  preference-routing convert <graph> --format json|geojson [--output <file>]
+      Write the graph in another format";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    GeoJson,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve {
+        graph: Option<String>,
+    },
+    Route {
+        graph: String,
+        waypoints: Vec<Coordinate>,
+        alpha: Preference,
+        format: Format,
+        output: Option<String>,
+    },
+    Learn {
+        graph: String,
+        routes: String,
+        output: Option<String>,
+    },
+    Validate {
+        graph: String,
+    },
+    Convert {
+        graph: String,
+        format: Format,
+        output: Option<String>,
+    },
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub config: Option<String>,
+    pub command: Command,
+}
+
+/// Parses the arguments without the name of the binary. The config can be
+/// given anywhere.
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut config = None;
+    let mut command_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let path = args
+                .next()
+                .ok_or_else(|| String::from("Missing value for --config"))?;
+            config = Some(path.clone());
+        } else {
+            command_args.push(arg.clone());
+        }
+    }
+    Ok(Args {
+        config,
+        command: parse_command(&command_args)?,
+    })
+}
+
+/// A single graph path is short for serve, as before there were subcommands
+fn parse_command(args: &[String]) -> Result<Command, String> {
+    let (command, args) = match args.split_first() {
+        None => return Ok(Command::Serve { graph: None }),
+        Some((command, args)) => (command.as_str(), args),
+    };
+    let mut positional = Vec::new();
+    let mut alpha = None;
+    let mut format = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--alpha" => alpha = Some(parse_alpha(value()?)?),
+            "--format" => format = Some(parse_format(value()?)?),
+            "--output" | "-o" => output = Some(value()?.clone()),
+            option if option.starts_with("--") => return Err(format!("Unknown option {}", option)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let command = match command {
+        "help" | "--help" | "-h" => Command::Help,
+        "serve" if positional.len() <= 1 => Command::Serve {
+            graph: positional.pop(),
+        },
+        "serve" => return Err(String::from("serve takes at most one graph")),
+        "route" => {
+            if positional.len() < 3 {
+                return Err(String::from(
+                    "route needs a graph and at least two waypoints",
+                ));
+            }
+            let waypoints = positional[1..]
+                .iter()
+                .map(|waypoint| parse_coordinate(waypoint))
+                .collect::<Result<_, _>>()?;
+            Command::Route {
+                graph: positional.remove(0),
+                waypoints,
+                alpha: alpha.ok_or_else(|| String::from("route needs --alpha"))?,
+                format: format.unwrap_or(Format::Json),
+                output,
+            }
+        }
+        "learn" if positional.len() == 2 => Command::Learn {
+            routes: positional.pop().unwrap(),
+            graph: positional.pop().unwrap(),
+            output,
+        },
+        "learn" => return Err(String::from("learn needs a graph and a route file")),
+        "validate" if positional.len() == 1 => Command::Validate {
+            graph: positional.pop().unwrap(),
+        },
+        "validate" => return Err(String::from("validate needs a graph")),
+        "convert" if positional.len() == 1 => Command::Convert {
+            graph: positional.pop().unwrap(),
+            format: format.ok_or_else(|| String::from("convert needs --format"))?,
+            output,
+        },
+        "convert" => return Err(String::from("convert needs a graph")),
+        graph if args_are_empty(&positional, alpha, format, &output) => Command::Serve {
+            graph: Some(String::from(graph)),
+        },
+        command => return Err(format!("Unknown command {}", command)),
+    };
+    Ok(command)
+}
+
+fn args_are_empty(
+    positional: &[String],
+    alpha: Option<Preference>,
+    format: Option<Format>,
+    output: &Option<String>,
+) -> bool {
+    positional.is_empty() && alpha.is_none() && format.is_none() && output.is_none()
+}
+
+fn parse_alpha(arg: &str) -> Result<Preference, String> {
+    let values: Vec<f64> = arg
+        .split(',')
+        .map(|value| value.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid alpha {}", arg))?;
+    if values.len() != EDGE_COST_DIMENSION {
+        return Err(format!(
+            "Alpha {} needs {} values",
+            arg, EDGE_COST_DIMENSION
+        ));
+    }
+    let mut alpha = [0.0; EDGE_COST_DIMENSION];
+    alpha.copy_from_slice(&values);
+    Ok(alpha)
+}
+
+fn parse_format(arg: &str) -> Result<Format, String> {
+    match arg {
+        "json" => Ok(Format::Json),
+        "geojson" => Ok(Format::GeoJson),
+        _ => Err(format!("Unknown format {}, use json or geojson", arg)),
+    }
+}
+
+fn parse_coordinate(arg: &str) -> Result<Coordinate, String> {
+    let values: Vec<&str> = arg.split(',').collect();
+    match values.as_slice() {
+        [lat, lng] => match (lat.trim().parse(), lng.trim().parse()) {
+            (Ok(lat), Ok(lng)) => Ok(Coordinate { lat, lng }),
+            _ => Err(format!("Invalid coordinate {}", arg)),
+        },
+        _ => Err(format!("Invalid coordinate {}, expected lat,lng", arg)),
+    }
+}
+
+/// Runs the command, errors are meant to be printed before exiting
+pub fn run(args: Args) -> Result<(), String> {
+    let Args { config, command } = args;
+    let needs_config = matches!(command, Command::Serve { .. } | Command::Learn { .. });
+    let config = if config.is_some() || needs_config {
+        let config = config::load_config(config.as_deref())?;
+        logging::init(config.log_level(), config.log_format());
+        Some(config)
+    } else {
+        logging::init(LevelFilter::Info, LogFormat::Text);
+        None
+    };
+    match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::Serve { graph } => serve(graph),
+        Command::Route {
+            graph,
+            waypoints,
+            alpha,
+            format,
+            output,
+        } => {
+            let graph = read_graph(&graph, config)?;
+            let path = graph
+                .find_shortest_path_alt(0, waypoints, alpha)
+                .ok_or_else(|| String::from("No route found between the waypoints"))?;
+            let value = match format {
+                Format::Json => json!(path),
+                Format::GeoJson => route_to_geojson(&path),
+            };
+            write_output(output.as_deref(), &value)
+        }
+        Command::Learn {
+            graph,
+            routes,
+            output,
+        } => {
+            let graph = read_graph(&graph, config)?;
+            let mut routes = read_routes(&routes)?;
+            let locations = index_locations(&graph);
+            for route in &mut routes {
+                if !route.matches(&graph) && !route.rematch(&graph, &locations) {
+                    return Err(format!("Route {} does not match the graph", route.id));
+                }
+                if route.nodes.len() < 2 {
+                    return Err(format!("Route {} has less than two nodes", route.id));
+                }
+                graph.find_preference(route);
+            }
+            write_output(output.as_deref(), &json!(routes))
+        }
+        Command::Validate { graph } => {
+            let graph = read_graph(&graph, config)?;
+            let num_of_shortcuts = graph
+                .edges
+                .iter()
+                .filter(|edge| edge.replaced_edges.is_some())
+                .count();
+            println!("Nodes: {}", graph.nodes.len());
+            println!("Edges: {}", graph.edges.len() - num_of_shortcuts);
+            println!("Shortcuts: {}", num_of_shortcuts);
+            println!("Turn rules: {}", graph.has_turn_rules());
+            println!("Bounding box: {:?}", graph.bounding_box());
+            println!("Fingerprint: {}", graph.fingerprint());
+            let problems = graph.validate();
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if problems.is_empty() {
+                println!("The graph is valid");
+                Ok(())
+            } else {
+                Err(format!("Found {} problems in the graph", problems.len()))
+            }
+        }
+        Command::Convert {
+            graph,
+            format,
+            output,
+        } => {
+            let graph = read_graph(&graph, config)?;
+            let value = match format {
+                Format::Json => graph_to_json(&graph),
+                Format::GeoJson => graph_to_geojson(&graph),
+            };
+            write_output(output.as_deref(), &value)
+        }
+    }
+}
+
+fn serve(graph_path: Option<String>) -> Result<(), String> {
+    let config = config::get_config();
+    let mut graphs = Vec::new();
+    if let Some(path) = graph_path {
+        graphs.push(load_graph("default", &path).map_err(|err| err.to_string())?);
+    }
+    for graph_config in config.graphs() {
+        graphs.push(
+            load_graph(graph_config.name(), graph_config.path())
+                .map_err(|err| format!("Could not load {}: {}", graph_config.path(), err))?,
+        );
+    }
+    if graphs.is_empty() {
+        return Err(String::from(
+            "Please provide the path to a graph file or list the graphs in config.toml",
+        ));
+    }
+    for (index, graph) in graphs.iter().enumerate() {
+        if graphs[..index]
+            .iter()
+            .any(|other| other.name() == graph.name())
+        {
+            return Err(format!(
+                "The graph name {} is used more than once",
+                graph.name()
+            ));
+        }
+    }
+    server::start_server(graphs);
+    Ok(())
+}
+
+/// Parses the graph and applies the settings of the config, if one was loaded
+fn read_graph(file_path: &str, config: Option<&AppConfig>) -> Result<Graph, String> {
+    let mut graph = parse_graph_file(file_path)
+        .map_err(|err| format!("Could not read graph {}: {}", file_path, err))?;
+    if let Some(config) = config {
+        graph.configure(config);
+    }
+    Ok(graph)
+}
+
+/// A route file contains a single route or a list of them
+fn read_routes(file_path: &str) -> Result<Vec<Path>, String> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|err| format!("Could not read {}: {}", file_path, err))?;
+    let value: Value = serde_json::from_str(&content)
+        .map_err(|err| format!("Could not parse {}: {}", file_path, err))?;
+    let routes = if value.is_array() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|route| vec![route])
+    };
+    routes.map_err(|err| format!("{} contains no valid routes: {}", file_path, err))
+}
+
+fn write_output(file_path: Option<&str>, value: &Value) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(value).map_err(|err| err.to_string())?;
+    match file_path {
+        Some(file_path) => File::create(file_path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map_err(|err| format!("Could not write {}: {}", file_path, err)),
+        None => writeln!(io::stdout(), "{}", content).map_err(|err| err.to_string()),
+    }
+}
+
+// GeoJSON positions are [lng, lat]
+fn position(coordinate: &Coordinate) -> Value {
+    json!([coordinate.lng, coordinate.lat])
+}
+
+fn route_to_geojson(path: &Path) -> Value {
+    let waypoints: Vec<Value> = path
+        .waypoints
+        .iter()
+        .map(|waypoint| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": position(waypoint) },
+                "properties": {}
+            })
+        })
+        .collect();
+    let line = json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "LineString",
+            "coordinates": path.coordinates.iter().map(position).collect::<Vec<_>>()
+        },
+        "properties": {
+            "costs": path.total_dimension_costs,
+            "alpha": path.user_split.alphas[0]
+        }
+    });
+    json!({
+        "type": "FeatureCollection",
+        "features": std::iter::once(line).chain(waypoints).collect::<Vec<_>>()
+    })
+}
+
+fn graph_to_json(graph: &Graph) -> Value {
+    let nodes: Vec<Value> = graph
+        .nodes
+        .iter()
+        .map(|node| {
+            json!({
+                "id": node.id,
+                "lat": node.location.lat,
+                "lng": node.location.lng,
+                "height": node.height,
+                "ch_level": node.ch_level
+            })
+        })
+        .collect();
+    let edges: Vec<Value> = graph
+        .edges
+        .iter()
+        .map(|edge| {
+            json!({
+                "id": edge.id,
+                "source": edge.source_id,
+                "target": edge.target_id,
+                "costs": edge.edge_costs,
+                "replaced_edges": edge.replaced_edges
+            })
+        })
+        .collect();
+    let turns: Vec<Value> = graph
+        .turns()
+        .into_iter()
+        .map(|(from_edge, to_edge, costs)| {
+            json!({ "from": from_edge, "to": to_edge, "costs": costs })
+        })
+        .collect();
+    json!({
+        "nodes": nodes,
+        "edges": edges,
+        "turns": turns,
+        "forbid_u_turns": graph.forbids_u_turns()
+    })
+}
+
+/// Original edges as lines and turns as points at the node they are taken at,
+/// shortcuts are left out. Restricted turns have no costs.
+fn graph_to_geojson(graph: &Graph) -> Value {
+    let mut features: Vec<Value> = graph
+        .edges
+        .iter()
+        .filter(|edge| edge.replaced_edges.is_none())
+        .map(|edge| {
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": [
+                        position(&graph.nodes[edge.source_id].location),
+                        position(&graph.nodes[edge.target_id].location)
+                    ]
+                },
+                "properties": { "id": edge.id, "costs": edge.edge_costs }
+            })
+        })
+        .collect();
+    features.extend(
+        graph
+            .turns()
+            .into_iter()
+            .map(|(from_edge, to_edge, costs)| {
+                let node = &graph.nodes[graph.edges[from_edge].target_id];
+                json!({
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": position(&node.location) },
+                    "properties": { "from": from_edge, "to": to_edge, "costs": costs }
+                })
+            }),
+    );
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use preference_routing::graph::generator::generate_grid_graph;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(Ok(Command::Serve { graph: None }), parse_command(&[]));
+        assert_eq!(
+            Ok(Command::Serve {
+                graph: Some(String::from("graph"))
+            }),
+            parse_command(&args("graph"))
+        );
+        assert_eq!(
+            Ok(Command::Route {
+                graph: String::from("graph"),
+                waypoints: vec![
+                    Coordinate { lat: 1.0, lng: 2.0 },
+                    Coordinate { lat: 3.0, lng: 4.5 }
+                ],
+                alpha: [1.0, 0.0, 0.5, 0.0],
+                format: Format::GeoJson,
+                output: None,
+            }),
+            parse_command(&args(
+                "route graph 1,2 --alpha 1,0,0.5,0 3,4.5 --format geojson"
+            ))
+        );
+        assert_eq!(
+            Ok(Command::Convert {
+                graph: String::from("graph"),
+                format: Format::Json,
+                output: Some(String::from("out.json")),
+            }),
+            parse_command(&args("convert graph --format json -o out.json"))
+        );
+    }
+
+    #[test]
+    fn config_path() {
+        let parsed = parse_args(&args("validate --config other.toml graph")).unwrap();
+        assert_eq!(Some(String::from("other.toml")), parsed.config);
+        assert_eq!(
+            Command::Validate {
+                graph: String::from("graph")
+            },
+            parsed.command
+        );
+        assert!(parse_args(&args("validate graph --config")).is_err());
+    }
+
+    #[test]
+    fn invalid_commands() {
+        assert!(parse_command(&args("route graph 1,2 3,4")).is_err());
+        assert!(parse_command(&args("route graph 1,2 --alpha 1,0,0")).is_err());
+        assert!(parse_command(&args("route graph 1 3,4 --alpha 1,0,0,0")).is_err());
+        assert!(parse_command(&args("convert graph --format xml")).is_err());
+        assert!(parse_command(&args("validate")).is_err());
+        assert!(parse_command(&args("validate graph other")).is_err());
+        assert!(parse_command(&args("serve graph other")).is_err());
+        assert!(parse_command(&args("learn graph")).is_err());
+        assert!(parse_command(&args("graph --output file")).is_err());
+    }
+
+    #[test]
+    fn geojson() {
+        let graph = generate_grid_graph(3, 3);
+        let path = graph
+            .find_shortest_path(0, vec![0, 8], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        let route = route_to_geojson(&path);
+        assert_eq!(3, route["features"].as_array().unwrap().len());
+        let line = &route["features"][0]["geometry"]["coordinates"];
+        assert_eq!(path.coordinates.len(), line.as_array().unwrap().len());
+        // node 8 lies at x = 2, y = 2
+        assert_eq!(json!([2.0, 2.0]), line[path.coordinates.len() - 1]);
+
+        let lines = graph_to_geojson(&graph);
+        assert_eq!(24, lines["features"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn turns() {
+        let graph = read_graph("./src/test_graphs/turnTestGraph", None).unwrap();
+        let value = graph_to_json(&graph);
+        assert_eq!(
+            json!([
+                { "from": 4, "to": 7, "costs": null },
+                { "from": 11, "to": 14, "costs": [0.5, 0.0, 0.0, 0.0] }
+            ]),
+            value["turns"]
+        );
+        assert_eq!(json!(false), value["forbid_u_turns"]);
+        let features = graph_to_geojson(&graph)["features"].clone();
+        let points: Vec<&Value> = features
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|feature| feature["geometry"]["type"] == json!("Point"))
+            .collect();
+        assert_eq!(2, points.len());
+        assert_eq!(json!(4), points[0]["properties"]["from"]);
+    }
+
+    #[test]
+    fn missing_graph() {
+        assert!(read_graph("./src/test_graphs/missing", None).is_err());
+        assert!(run(Args {
+            config: None,
+            command: Command::Validate {
+                graph: String::from("./src/test_graphs/missing")
+            }
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn malformed_graph() {
+        let content = std::fs::read_to_string("./src/test_graphs/testGraph").unwrap();
+        let path = std::env::temp_dir().join("preference-routing-cli-malformed-test");
+        let path = path.to_str().unwrap();
+        // the header, the nodes and 6 of the edges
+        let lines: Vec<&str> = content.lines().take(25).collect();
+        std::fs::write(path, lines.join("\n")).unwrap();
+        let message = read_graph(path, None).unwrap_err();
+        let _ = std::fs::remove_file(path);
+        assert!(message.contains("ends after"), "{}", message);
+    }
+}
@@ -1,30 +1,212 @@
+use crate::graph::QueryMode;
 use crate::helpers::Preference;
+use crate::logging::LogFormat;
+use crate::EDGE_COST_DIMENSION;
+use log::LevelFilter;
 use serde::Deserialize;
-use std::fs::File;
-use std::io::Read;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
 
-static mut INSTANCE: Option<AppConfig> = None;
+static INSTANCE: OnceLock<AppConfig> = OnceLock::new();
 
-#[derive(Deserialize)]
+const DEFAULT_PATH: &str = "config.toml";
+
+// environment variables override the fields of the file
+const ENV_PREFIX: &str = "PREFERENCE_ROUTING_";
+// names the config file if it is not given on the command line
+const ENV_PATH: &str = "PREFERENCE_ROUTING_CONFIG";
+
+#[derive(Clone, Copy)]
+enum FieldKind {
+    Text,
+    Bool,
+    Integer,
+    // comma separated
+    TextList,
+    FloatList,
+    // comma separated name=path pairs
+    Graphs,
+}
+
+const FIELDS: [(&str, FieldKind); 14] = [
+    ("port", FieldKind::Text),
+    ("database_path", FieldKind::Text),
+    ("edge_cost_tags", FieldKind::TextList),
+    ("initial_pref", FieldKind::FloatList),
+    ("query_mode", FieldKind::Text),
+    ("verify_queries", FieldKind::Bool),
+    ("precompute_unpacking", FieldKind::Bool),
+    ("forbid_u_turns", FieldKind::Bool),
+    ("graphs", FieldKind::Graphs),
+    ("log_level", FieldKind::Text),
+    ("log_format", FieldKind::Text),
+    ("normalize_preferences", FieldKind::Bool),
+    ("learning_workers", FieldKind::Integer),
+    ("admin_token", FieldKind::Text),
+];
+
+#[derive(Debug, Deserialize)]
+pub struct GraphConfig {
+    name: String,
+    path: String,
+}
+
+impl GraphConfig {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[derive(Debug, Deserialize)]
 pub struct AppConfig {
     port: String,
     database_path: String,
     edge_cost_tags: Vec<String>,
-    initial_pref: Preference,
+    initial_pref: Vec<f64>,
+    #[serde(default)]
+    query_mode: QueryMode,
+    #[serde(default)]
+    verify_queries: bool,
+    #[serde(default)]
+    precompute_unpacking: bool,
+    #[serde(default)]
+    forbid_u_turns: bool,
+    #[serde(default)]
+    graphs: Vec<GraphConfig>,
+    #[serde(default = "default_log_level")]
+    log_level: String,
+    #[serde(default)]
+    log_format: LogFormat,
+    #[serde(default = "default_normalize_preferences")]
+    normalize_preferences: bool,
+    #[serde(default = "default_learning_workers")]
+    learning_workers: usize,
+    // administrative endpoints are disabled without it
+    #[serde(default)]
+    admin_token: Option<String>,
+}
+
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+fn default_normalize_preferences() -> bool {
+    true
+}
+
+fn default_learning_workers() -> usize {
+    2
 }
 
 impl AppConfig {
-    fn new() -> Self {
-        match File::open("config.toml") {
-            Ok(mut file) => {
-                let mut file_content = String::new();
-                file.read_to_string(&mut file_content)
-                    .expect("Could not read config file");
-                let config: AppConfig =
-                    toml::from_str(&file_content).expect("Could not parse config");
-                config
+    /// Reads the config file, applies the overrides of the environment and
+    /// validates the result. A missing file is only fine at the default path,
+    /// then all required fields have to come from the environment.
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let explicit_path = path.map(String::from).or_else(|| env::var(ENV_PATH).ok());
+        let path = explicit_path.as_deref().unwrap_or(DEFAULT_PATH);
+        let content = match fs::read_to_string(path) {
+            Ok(content) => Some(content),
+            Err(_) if explicit_path.is_none() => None,
+            Err(err) => return Err(format!("Could not read config {}: {}", path, err)),
+        };
+        Self::parse(content.as_deref(), &|name| env::var(name).ok())
+            .map_err(|message| format!("Invalid config {}: {}", path, message))
+    }
+
+    /// Parses the content of a config file, the variables are looked up with env
+    fn parse(content: Option<&str>, env: &dyn Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut table = match content {
+            Some(content) => toml::from_str(content).map_err(|err| err.to_string())?,
+            None => toml::value::Table::new(),
+        };
+        for (field, kind) in FIELDS.iter() {
+            let name = format!("{}{}", ENV_PREFIX, field.to_uppercase());
+            if let Some(value) = env(&name) {
+                let value = parse_env_value(&value, *kind)
+                    .map_err(|message| format!("{} {}", name, message))?;
+                table.insert(String::from(*field), value);
             }
-            Err(_err) => panic!("config.toml is missing"),
+        }
+        let config: AppConfig = toml::Value::Table(table)
+            .try_into()
+            .map_err(|err| err.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reports all invalid fields at once
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if self.port.parse::<u16>().is_err() {
+            problems.push(format!("port {} is not a valid port number", self.port));
+        }
+        if self.database_path.is_empty() {
+            problems.push(String::from("database_path is empty"));
+        }
+        if self.edge_cost_tags.len() != EDGE_COST_DIMENSION {
+            problems.push(format!(
+                "edge_cost_tags has {} tags, but edges have {} costs",
+                self.edge_cost_tags.len(),
+                EDGE_COST_DIMENSION
+            ));
+        }
+        if self.initial_pref.len() != self.edge_cost_tags.len() {
+            problems.push(format!(
+                "initial_pref has {} values, but there are {} edge_cost_tags",
+                self.initial_pref.len(),
+                self.edge_cost_tags.len()
+            ));
+        }
+        if self
+            .initial_pref
+            .iter()
+            .any(|value| !value.is_finite() || *value < 0.0)
+        {
+            problems.push(format!(
+                "initial_pref {:?} must be finite and not negative",
+                self.initial_pref
+            ));
+        }
+        let sum: f64 = self.initial_pref.iter().sum();
+        if (sum - 1.0).abs() > 1e-6 {
+            problems.push(format!(
+                "initial_pref {:?} sums to {} instead of 1",
+                self.initial_pref, sum
+            ));
+        }
+        if self.log_level.parse::<LevelFilter>().is_err() {
+            problems.push(format!(
+                "log_level {} is not one of off, error, warn, info, debug, trace",
+                self.log_level
+            ));
+        }
+        if self.learning_workers == 0 {
+            problems.push(String::from("learning_workers must be at least 1"));
+        }
+        if self.admin_token.as_deref() == Some("") {
+            problems.push(String::from("admin_token is empty"));
+        }
+        for (index, graph) in self.graphs.iter().enumerate() {
+            if graph.name.is_empty() || graph.path.is_empty() {
+                problems.push(format!("graphs[{}] needs a name and a path", index));
+            }
+            if self.graphs[..index]
+                .iter()
+                .any(|other| other.name == graph.name)
+            {
+                problems.push(format!("graph name {} is used more than once", graph.name));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join(", "))
         }
     }
 
@@ -41,15 +223,239 @@ impl AppConfig {
     }
 
     pub fn initial_pref(&self) -> Preference {
-        self.initial_pref
+        let mut pref = [0.0; EDGE_COST_DIMENSION];
+        pref.copy_from_slice(&self.initial_pref);
+        pref
+    }
+
+    pub fn query_mode(&self) -> QueryMode {
+        self.query_mode
+    }
+
+    pub fn verify_queries(&self) -> bool {
+        self.verify_queries
+    }
+
+    pub fn precompute_unpacking(&self) -> bool {
+        self.precompute_unpacking
+    }
+
+    pub fn forbid_u_turns(&self) -> bool {
+        self.forbid_u_turns
+    }
+
+    pub fn graphs(&self) -> &[GraphConfig] {
+        &self.graphs
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level.parse().unwrap_or(LevelFilter::Info)
+    }
+
+    pub fn log_format(&self) -> LogFormat {
+        self.log_format
+    }
+
+    /// Whether preferences of requests are scaled to a sum of 1
+    pub fn normalize_preferences(&self) -> bool {
+        self.normalize_preferences
+    }
+
+    /// Number of threads that run learning jobs
+    pub fn learning_workers(&self) -> usize {
+        self.learning_workers
+    }
+
+    /// Token of the administrative endpoints, which are disabled without one
+    pub fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref()
+    }
+}
+
+fn parse_env_value(value: &str, kind: FieldKind) -> Result<toml::Value, String> {
+    let list = || {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+    };
+    match kind {
+        FieldKind::Text => Ok(toml::Value::String(String::from(value))),
+        FieldKind::Bool => value
+            .parse()
+            .map(toml::Value::Boolean)
+            .map_err(|_| format!("must be true or false, not {}", value)),
+        FieldKind::Integer => value
+            .parse()
+            .map(toml::Value::Integer)
+            .map_err(|_| format!("must be a whole number, not {}", value)),
+        FieldKind::TextList => Ok(toml::Value::Array(
+            list()
+                .map(|item| toml::Value::String(String::from(item)))
+                .collect(),
+        )),
+        FieldKind::FloatList => list()
+            .map(|item| {
+                item.parse()
+                    .map(toml::Value::Float)
+                    .map_err(|_| format!("contains {}, which is not a number", item))
+            })
+            .collect::<Result<_, _>>()
+            .map(toml::Value::Array),
+        FieldKind::Graphs => list()
+            .map(|item| match item.split_once('=') {
+                Some((name, path)) => {
+                    let mut graph = toml::value::Table::new();
+                    graph.insert(String::from("name"), toml::Value::String(name.into()));
+                    graph.insert(String::from("path"), toml::Value::String(path.into()));
+                    Ok(toml::Value::Table(graph))
+                }
+                None => Err(format!("contains {}, expected name=path", item)),
+            })
+            .collect::<Result<_, _>>()
+            .map(toml::Value::Array),
     }
 }
 
+/// Loads the config once, later calls return the loaded one and fail if they
+/// ask for another path
+pub fn load_config(path: Option<&str>) -> Result<&'static AppConfig, String> {
+    if let Some(config) = INSTANCE.get() {
+        return match path {
+            None => Ok(config),
+            Some(path) => Err(format!(
+                "The config is already loaded, {} can not be used anymore",
+                path
+            )),
+        };
+    }
+    let config = AppConfig::load(path)?;
+    Ok(INSTANCE.get_or_init(|| config))
+}
+
+/// The loaded config, or the one from the default location if none was loaded
 pub fn get_config() -> &'static AppConfig {
-    unsafe {
-        if INSTANCE.is_none() {
-            INSTANCE = Some(AppConfig::new());
-        }
-        INSTANCE.as_ref().unwrap()
+    INSTANCE.get_or_init(|| AppConfig::load(None).unwrap_or_else(|message| panic!("{}", message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const CONTENT: &str = r#"
+        port = "8000"
+        database_path = "database"
+        edge_cost_tags = ["Distance", "Unit", "Height", "UnsuitDist"]
+        initial_pref = [1.0, 0.0, 0.0, 0.0]
+    "#;
+
+    fn parse(content: Option<&str>, vars: &[(&str, &str)]) -> Result<AppConfig, String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (String::from(*name), String::from(*value)))
+            .collect();
+        AppConfig::parse(content, &|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn env_overrides() {
+        let config = parse(
+            Some(CONTENT),
+            &[
+                ("PREFERENCE_ROUTING_PORT", "9000"),
+                ("PREFERENCE_ROUTING_INITIAL_PREF", "0.5, 0.5, 0, 0"),
+                ("PREFERENCE_ROUTING_QUERY_MODE", "plain"),
+                ("PREFERENCE_ROUTING_FORBID_U_TURNS", "true"),
+                ("PREFERENCE_ROUTING_GRAPHS", "north=n.graph,south=s.graph"),
+                ("PREFERENCE_ROUTING_LOG_LEVEL", "debug"),
+                ("PREFERENCE_ROUTING_LOG_FORMAT", "json"),
+                ("PREFERENCE_ROUTING_NORMALIZE_PREFERENCES", "false"),
+                ("PREFERENCE_ROUTING_LEARNING_WORKERS", "4"),
+                ("PREFERENCE_ROUTING_ADMIN_TOKEN", "secret"),
+            ],
+        )
+        .unwrap();
+        assert_eq!("9000", config.port());
+        assert_eq!("database", config.database_path());
+        assert_eq!([0.5, 0.5, 0.0, 0.0], config.initial_pref());
+        assert_eq!(QueryMode::Plain, config.query_mode());
+        assert!(config.forbid_u_turns());
+        assert_eq!(2, config.graphs().len());
+        assert_eq!("s.graph", config.graphs()[1].path());
+        assert_eq!(LevelFilter::Debug, config.log_level());
+        assert_eq!(LogFormat::Json, config.log_format());
+        assert!(!config.normalize_preferences());
+        assert_eq!(4, config.learning_workers());
+        assert_eq!(Some("secret"), config.admin_token());
+        let config = parse(Some(CONTENT), &[]).unwrap();
+        assert!(config.normalize_preferences());
+        assert_eq!(None, config.admin_token());
+    }
+
+    #[test]
+    fn without_file() {
+        assert!(parse(None, &[]).unwrap_err().contains("missing field"));
+        let config = parse(
+            None,
+            &[
+                ("PREFERENCE_ROUTING_PORT", "8000"),
+                ("PREFERENCE_ROUTING_DATABASE_PATH", "database"),
+                ("PREFERENCE_ROUTING_EDGE_COST_TAGS", "a,b,c,d"),
+                ("PREFERENCE_ROUTING_INITIAL_PREF", "0,0,0,1"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(4, config.edge_cost_tags().len());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let message = parse(
+            Some(CONTENT),
+            &[
+                ("PREFERENCE_ROUTING_PORT", "eighty"),
+                ("PREFERENCE_ROUTING_INITIAL_PREF", "1.5,-0.5,0"),
+                ("PREFERENCE_ROUTING_LOG_LEVEL", "loud"),
+                ("PREFERENCE_ROUTING_LEARNING_WORKERS", "0"),
+                ("PREFERENCE_ROUTING_ADMIN_TOKEN", ""),
+            ],
+        )
+        .unwrap_err();
+        assert!(message.contains("port eighty is not a valid port number"));
+        assert!(message.contains("initial_pref has 3 values, but there are 4 edge_cost_tags"));
+        assert!(message.contains("[1.5, -0.5, 0.0] must be finite and not negative"));
+        assert!(message.contains("log_level loud is not one of"));
+        assert!(message.contains("learning_workers must be at least 1"));
+        assert!(message.contains("admin_token is empty"));
+
+        let message = parse(
+            Some(CONTENT),
+            &[("PREFERENCE_ROUTING_INITIAL_PREF", "0.5,0,0,0")],
+        )
+        .unwrap_err();
+        assert!(message.contains("sums to 0.5 instead of 1"));
+
+        let message = parse(
+            Some(CONTENT),
+            &[("PREFERENCE_ROUTING_INITIAL_PREF", "NaN,0,0,1")],
+        )
+        .unwrap_err();
+        assert!(message.contains("must be finite and not negative"));
+
+        let message = parse(
+            Some(CONTENT),
+            &[("PREFERENCE_ROUTING_VERIFY_QUERIES", "yes")],
+        )
+        .unwrap_err();
+        assert!(message.starts_with("PREFERENCE_ROUTING_VERIFY_QUERIES must be true or false"));
+    }
+
+    #[test]
+    fn repository_config() {
+        assert!(AppConfig::load(Some("config.toml")).is_ok());
+        assert!(AppConfig::load(Some("missing.toml"))
+            .unwrap_err()
+            .starts_with("Could not read config missing.toml"));
     }
 }
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::graph::Graph;
+use crate::helpers::Coordinate;
+
+// Parts of the graph that a single query must not use
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Avoid {
+    #[serde(default)]
+    pub edges: Vec<usize>,
+    #[serde(default)]
+    pub nodes: Vec<usize>,
+    // nodes inside any of the polygons are avoided
+    #[serde(default)]
+    pub polygons: Vec<Vec<Coordinate>>,
+}
+
+impl Avoid {
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty() && self.nodes.is_empty() && self.polygons.is_empty()
+    }
+
+    /// The polygons without the ids, which only mean the same on graphs with the
+    /// same fingerprint
+    pub fn polygons_only(&self) -> Avoid {
+        Avoid {
+            polygons: self.polygons.clone(),
+            ..Avoid::default()
+        }
+    }
+
+    /// Flags every original edge that must not be used, or returns None if nothing
+    /// is blocked. Original edges are blocked if they are listed, part of a listed
+    /// shortcut or touch an avoided node. Shortcuts are never flagged, queries with
+    /// blocked edges only search the original edges. Unknown ids are ignored.
+    pub fn find_blocked_edges(&self, graph: &Graph) -> Option<Vec<bool>> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut blocked_nodes = vec![false; graph.nodes.len()];
+        for node in &self.nodes {
+            if let Some(blocked) = blocked_nodes.get_mut(*node) {
+                *blocked = true;
+            }
+        }
+        if !self.polygons.is_empty() {
+            for node in &graph.nodes {
+                if self
+                    .polygons
+                    .iter()
+                    .any(|polygon| contains(polygon, &node.location))
+                {
+                    blocked_nodes[node.id] = true;
+                }
+            }
+        }
+
+        let mut blocked = vec![false; graph.edges.len()];
+        let mut unpacked = Vec::new();
+        for edge in self.edges.iter().filter(|edge| **edge < graph.edges.len()) {
+            // a listed shortcut stands for all of its original edges
+            unpacked.clear();
+            graph.unpack_edge(*edge, &mut unpacked);
+            unpacked.iter().for_each(|orig| blocked[*orig] = true);
+        }
+        for edge in graph
+            .edges
+            .iter()
+            .filter(|edge| edge.replaced_edges.is_none())
+        {
+            if blocked_nodes[edge.source_id] || blocked_nodes[edge.target_id] {
+                blocked[edge.id] = true;
+            }
+        }
+        if !blocked.iter().any(|edge| *edge) {
+            return None;
+        }
+        Some(blocked)
+    }
+}
+
+/// Even-odd rule, the polygon is closed implicitly
+fn contains(polygon: &[Coordinate], point: &Coordinate) -> bool {
+    let mut inside = false;
+    for (index, current) in polygon.iter().enumerate() {
+        let previous = &polygon[(index + polygon.len() - 1) % polygon.len()];
+        if (current.lat > point.lat) != (previous.lat > point.lat) {
+            let lng = current.lng
+                + (point.lat - current.lat) / (previous.lat - current.lat)
+                    * (previous.lng - current.lng);
+            if point.lng < lng {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::parse_graph_file;
+
+    fn square(min: f64, max: f64) -> Vec<Coordinate> {
+        vec![
+            Coordinate { lat: min, lng: min },
+            Coordinate { lat: min, lng: max },
+            Coordinate { lat: max, lng: max },
+            Coordinate { lat: max, lng: min },
+        ]
+    }
+
+    #[test]
+    fn polygon_contains() {
+        let polygon = square(0.0, 2.0);
+        assert!(contains(&polygon, &Coordinate { lat: 1.0, lng: 1.0 }));
+        assert!(!contains(&polygon, &Coordinate { lat: 3.0, lng: 1.0 }));
+        assert!(!contains(
+            &polygon,
+            &Coordinate {
+                lat: 1.0,
+                lng: -1.0
+            }
+        ));
+    }
+
+    #[test]
+    fn blocked_shortcuts() {
+        let graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        assert!(Avoid::default().find_blocked_edges(&graph).is_none());
+
+        // shortcut 19 stands for its original edges, the shortcut itself is not flagged
+        let avoid = Avoid {
+            edges: vec![19],
+            ..Avoid::default()
+        };
+        let blocked = avoid.find_blocked_edges(&graph).unwrap();
+        let blocked: Vec<usize> = (0..blocked.len()).filter(|edge| blocked[*edge]).collect();
+        let mut unpacked = Vec::new();
+        graph.unpack_edge(19, &mut unpacked);
+        unpacked.sort();
+        assert!(unpacked.contains(&12));
+        assert_eq!(unpacked, blocked);
+    }
+}
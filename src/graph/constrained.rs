@@ -0,0 +1,432 @@
+use std::cmp::Reverse;
+use std::collections::binary_heap::BinaryHeap;
+
+use ordered_float::OrderedFloat;
+
+use crate::graph::dijkstra::HalfPath;
+use crate::graph::path::Path;
+use crate::graph::Graph;
+use crate::helpers::{add_edge_costs, costs_by_alpha, Costs, Preference};
+use crate::EDGE_COST_DIMENSION;
+
+// Upper limits for the total costs of a route per dimension, None if unlimited
+pub type Budgets = [Option<f64>; EDGE_COST_DIMENSION];
+
+const EPSILON: f64 = 1e-9;
+
+// Iterations of the lagrangian relaxation to find a good feasible route upfront
+const LAGRANGIAN_ITERATIONS: usize = 10;
+
+#[derive(Debug)]
+pub enum ConstrainedResult {
+    Found(Box<Path>),
+    // dimensions whose budgets can not be kept
+    Infeasible(Vec<usize>),
+    NoRoute,
+}
+
+struct Label {
+    node_id: usize,
+    costs: Costs,
+    total_cost: f64,
+    // previous label and the edge leading from it
+    previous: Option<(usize, usize)>,
+    dominated: bool,
+}
+
+/// Finds the route with the lowest cost by alpha that stays within the budgets.
+///
+/// The search first tries to find feasible routes with the contraction
+/// hierarchy by penalizing the violated dimensions (lagrangian relaxation).
+/// The best of those bounds a label-setting search over the cost vectors on the
+/// original edges, which only keeps labels that are not dominated in the total
+/// cost and the budgeted dimensions. With turn rules, a label only competes with
+/// those that arrived on the same edge.
+pub struct ConstrainedSearch<'a> {
+    graph: &'a Graph,
+    alpha: Preference,
+    budgets: Budgets,
+    // lower bounds for the remaining costs to the target, by alpha and per dimension
+    lower_bound: Vec<f64>,
+    dimension_lower_bounds: Vec<Option<Vec<f64>>>,
+}
+
+impl<'a> ConstrainedSearch<'a> {
+    pub fn new(graph: &'a Graph, alpha: Preference, budgets: Budgets) -> Self {
+        ConstrainedSearch {
+            graph,
+            alpha,
+            budgets,
+            lower_bound: Vec::new(),
+            dimension_lower_bounds: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, source: usize, target: usize) -> ConstrainedResult {
+        self.lower_bound = self.calc_lower_bounds(target, self.alpha);
+        if self.lower_bound[source] == std::f64::MAX {
+            return ConstrainedResult::NoRoute;
+        }
+        self.dimension_lower_bounds = (0..EDGE_COST_DIMENSION)
+            .map(|dim| {
+                self.budgets[dim].map(|_| {
+                    let mut unit = [0.0; EDGE_COST_DIMENSION];
+                    unit[dim] = 1.0;
+                    self.calc_lower_bounds(target, unit)
+                })
+            })
+            .collect();
+        let violated: Vec<usize> = (0..EDGE_COST_DIMENSION)
+            .filter(
+                |dim| match (self.budgets[*dim], &self.dimension_lower_bounds[*dim]) {
+                    (Some(limit), Some(bounds)) => bounds[source] > limit + EPSILON,
+                    _ => false,
+                },
+            )
+            .collect();
+        if !violated.is_empty() {
+            return ConstrainedResult::Infeasible(violated);
+        }
+
+        let best_feasible = self.find_lagrangian_path(source, target);
+        if let Some((true, path)) = best_feasible {
+            // the unconstrained optimum already keeps all budgets
+            return ConstrainedResult::Found(Box::new(path));
+        }
+        let upper_bound = best_feasible
+            .as_ref()
+            .map(|(_, path)| costs_by_alpha(path.total_dimension_costs, self.alpha));
+
+        match self.label_search(source, target, upper_bound) {
+            Some(edges) => {
+                ConstrainedResult::Found(Box::new(self.make_path(source, target, edges)))
+            }
+            None => match best_feasible {
+                Some((_, path)) => ConstrainedResult::Found(Box::new(path)),
+                None => ConstrainedResult::Infeasible(
+                    (0..EDGE_COST_DIMENSION)
+                        .filter(|dim| self.budgets[*dim].is_some())
+                        .collect(),
+                ),
+            },
+        }
+    }
+
+    /// Returns the cheapest feasible route found by penalizing violated
+    /// dimensions, and whether it is the unpenalized optimum
+    fn find_lagrangian_path(&self, source: usize, target: usize) -> Option<(bool, Path)> {
+        let mut best: Option<(bool, Path)> = None;
+        let mut weights = self.alpha;
+        for iteration in 0..LAGRANGIAN_ITERATIONS {
+            let path = self
+                .graph
+                .find_shortest_path(0, vec![source, target], weights)?;
+            let costs = path.total_dimension_costs;
+            if self.is_feasible(costs) {
+                let cost = costs_by_alpha(costs, self.alpha);
+                let better = match &best {
+                    Some((_, best_path)) => {
+                        cost < costs_by_alpha(best_path.total_dimension_costs, self.alpha)
+                    }
+                    None => true,
+                };
+                if better {
+                    best = Some((iteration == 0, path));
+                }
+                if iteration == 0 {
+                    break;
+                }
+            }
+
+            // subgradient step, scaled so that a penalty is comparable to the costs by alpha
+            let scale = costs_by_alpha(costs, self.alpha).max(EPSILON);
+            for dim in 0..EDGE_COST_DIMENSION {
+                if let Some(limit) = self.budgets[dim] {
+                    let violation = (costs[dim] - limit) / limit.max(EPSILON);
+                    let step = scale / limit.max(EPSILON) * violation / (iteration + 1) as f64;
+                    weights[dim] = (weights[dim] + step).max(self.alpha[dim]);
+                }
+            }
+        }
+        best
+    }
+
+    fn label_search(
+        &self,
+        source: usize,
+        target: usize,
+        upper_bound: Option<f64>,
+    ) -> Option<Vec<usize>> {
+        let upper_bound = upper_bound.unwrap_or(std::f64::MAX);
+        let mut labels = vec![Label {
+            node_id: source,
+            costs: [0.0; EDGE_COST_DIMENSION],
+            total_cost: 0.0,
+            previous: None,
+            dominated: false,
+        }];
+        // labels that may dominate each other, with turn rules only those that
+        // arrive on the same edge
+        let turns = self.graph.has_turn_rules();
+        let num_of_groups = if turns {
+            self.graph.edges.len()
+        } else {
+            self.graph.nodes.len()
+        };
+        let mut grouped_labels: Vec<Vec<usize>> = vec![Vec::new(); num_of_groups];
+        if !turns {
+            grouped_labels[source].push(0);
+        }
+        let mut candidates = BinaryHeap::new();
+        candidates.push((Reverse(OrderedFloat(self.lower_bound[source])), 0));
+
+        while let Some((_, label_idx)) = candidates.pop() {
+            if labels[label_idx].dominated {
+                continue;
+            }
+            let node_id = labels[label_idx].node_id;
+            if node_id == target {
+                return Some(self.make_edge_path(&labels, label_idx));
+            }
+            let costs = labels[label_idx].costs;
+            let total_cost = labels[label_idx].total_cost;
+            let incoming = labels[label_idx].previous.map(|(_, edge_id)| edge_id);
+            for half_edge in self.graph.get_edges_out(node_id) {
+                let next_node = half_edge.target_id;
+                if self.lower_bound[next_node] == std::f64::MAX {
+                    continue;
+                }
+                let turn_costs = match incoming {
+                    Some(incoming) if turns => {
+                        match self.graph.get_turn_costs(incoming, half_edge.edge_id) {
+                            Some(turn_costs) => turn_costs,
+                            None => continue,
+                        }
+                    }
+                    _ => [0.0; EDGE_COST_DIMENSION],
+                };
+                let step_costs = add_edge_costs(turn_costs, half_edge.edge_costs);
+                let next_costs = add_edge_costs(costs, step_costs);
+                let next_total_cost = total_cost + costs_by_alpha(step_costs, self.alpha);
+                if next_total_cost + self.lower_bound[next_node] > upper_bound + EPSILON
+                    || !self.within_budgets(next_node, next_costs)
+                {
+                    continue;
+                }
+                let group = if turns { half_edge.edge_id } else { next_node };
+                let is_dominated = grouped_labels[group].iter().any(|other| {
+                    let other = &labels[*other];
+                    self.dominates(other.total_cost, other.costs, next_total_cost, next_costs)
+                });
+                if is_dominated {
+                    continue;
+                }
+                for other in &grouped_labels[group] {
+                    let other = &mut labels[*other];
+                    if self.dominates(next_total_cost, next_costs, other.total_cost, other.costs) {
+                        other.dominated = true;
+                    }
+                }
+                grouped_labels[group].retain(|other| !labels[*other].dominated);
+
+                let next_idx = labels.len();
+                labels.push(Label {
+                    node_id: next_node,
+                    costs: next_costs,
+                    total_cost: next_total_cost,
+                    previous: Some((label_idx, half_edge.edge_id)),
+                    dominated: false,
+                });
+                grouped_labels[group].push(next_idx);
+                candidates.push((
+                    Reverse(OrderedFloat(next_total_cost + self.lower_bound[next_node])),
+                    next_idx,
+                ));
+            }
+        }
+        None
+    }
+
+    /// Only the total cost and the budgeted dimensions are relevant for dominance
+    fn dominates(
+        &self,
+        total_cost: f64,
+        costs: Costs,
+        other_total_cost: f64,
+        other_costs: Costs,
+    ) -> bool {
+        total_cost <= other_total_cost
+            && (0..EDGE_COST_DIMENSION)
+                .filter(|dim| self.budgets[*dim].is_some())
+                .all(|dim| costs[dim] <= other_costs[dim])
+    }
+
+    fn within_budgets(&self, node_id: usize, costs: Costs) -> bool {
+        (0..EDGE_COST_DIMENSION).all(|dim| {
+            match (self.budgets[dim], &self.dimension_lower_bounds[dim]) {
+                (Some(limit), Some(bounds)) => costs[dim] + bounds[node_id] <= limit + EPSILON,
+                _ => true,
+            }
+        })
+    }
+
+    fn is_feasible(&self, costs: Costs) -> bool {
+        (0..EDGE_COST_DIMENSION).all(|dim| match self.budgets[dim] {
+            Some(limit) => costs[dim] <= limit + EPSILON,
+            None => true,
+        })
+    }
+
+    /// Costs from every node to the target on the original edges, weighted by alpha
+    fn calc_lower_bounds(&self, target: usize, alpha: Preference) -> Vec<f64> {
+        let mut costs = vec![std::f64::MAX; self.graph.nodes.len()];
+        let mut candidates = BinaryHeap::new();
+        costs[target] = 0.0;
+        candidates.push((Reverse(OrderedFloat(0.0)), target));
+        while let Some((Reverse(OrderedFloat(cost)), node_id)) = candidates.pop() {
+            if cost > costs[node_id] {
+                continue;
+            }
+            for half_edge in self.graph.get_edges_in(node_id) {
+                let next_cost = cost + costs_by_alpha(half_edge.edge_costs, alpha);
+                if next_cost < costs[half_edge.target_id] {
+                    costs[half_edge.target_id] = next_cost;
+                    candidates.push((Reverse(OrderedFloat(next_cost)), half_edge.target_id));
+                }
+            }
+        }
+        costs
+    }
+
+    fn make_edge_path(&self, labels: &[Label], label_idx: usize) -> Vec<usize> {
+        let mut edges = Vec::new();
+        let mut previous = labels[label_idx].previous;
+        while let Some((label_idx, edge_id)) = previous {
+            edges.push(edge_id);
+            previous = labels[label_idx].previous;
+        }
+        edges.reverse();
+        edges
+    }
+
+    fn make_path(&self, source: usize, target: usize, edges: Vec<usize>) -> Path {
+        let mut costs = edges.iter().fold([0.0; EDGE_COST_DIMENSION], |acc, edge| {
+            add_edge_costs(acc, self.graph.edges[*edge].edge_costs)
+        });
+        if self.graph.has_turn_rules() {
+            for win in edges.windows(2) {
+                if let Some(turn_costs) = self.graph.get_turn_costs(win[0], win[1]) {
+                    costs = add_edge_costs(costs, turn_costs);
+                }
+            }
+        }
+        let result = HalfPath {
+            edges: vec![edges],
+            dimension_costs: vec![costs],
+            total_dimension_costs: costs,
+            costs_by_alpha: vec![costs_by_alpha(costs, self.alpha)],
+        };
+        self.graph
+            .make_path(0, &[source, target], result, self.alpha)
+            .expect("The path has both waypoints")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::parse_graph_file;
+
+    fn get_graph() -> Graph {
+        parse_graph_file("./src/test_graphs/testGraph").unwrap()
+    }
+
+    #[test]
+    fn unconstrained_optimum() {
+        let graph = get_graph();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None, None, Some(4.0), None]);
+        match search.run(2, 10) {
+            ConstrainedResult::Found(path) => assert_eq!(vec![4, 7, 9, 12], path.edges),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn budget_changes_route() {
+        let graph = get_graph();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None, None, Some(3.5), None]);
+        match search.run(2, 10) {
+            ConstrainedResult::Found(path) => {
+                assert_eq!(vec![5, 9, 12], path.edges);
+                assert_eq!([5.0, 5.0, 3.0, 3.0], path.total_dimension_costs);
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn label_search() {
+        let graph = get_graph();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None, None, Some(3.5), None]);
+        search.lower_bound = search.calc_lower_bounds(10, search.alpha);
+        search.dimension_lower_bounds = vec![
+            None,
+            None,
+            Some(search.calc_lower_bounds(10, [0.0, 0.0, 1.0, 0.0])),
+            None,
+        ];
+        assert_eq!(Some(vec![5, 9, 12]), search.label_search(2, 10, None));
+    }
+
+    #[test]
+    fn turn_rules() {
+        let mut graph = get_graph();
+        // edge 4 is 2 -> 4, edge 7 is 4 -> 5, the turn breaks the budget of [4, 7, 9, 12]
+        graph.add_turn(4, 7, Some([0.0, 0.0, 2.0, 0.0])).unwrap();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None, None, Some(5.0), None]);
+        search.lower_bound = search.calc_lower_bounds(10, search.alpha);
+        search.dimension_lower_bounds = vec![
+            None,
+            None,
+            Some(search.calc_lower_bounds(10, [0.0, 0.0, 1.0, 0.0])),
+            None,
+        ];
+        assert_eq!(Some(vec![5, 9, 12]), search.label_search(2, 10, None));
+
+        // edge 5 is 2 -> 5, edge 9 is 5 -> 7
+        let mut graph = get_graph();
+        graph.add_turn(5, 9, None).unwrap();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None, None, Some(3.5), None]);
+        match search.run(2, 10) {
+            ConstrainedResult::Infeasible(dims) => assert_eq!(vec![2], dims),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn infeasible() {
+        let graph = get_graph();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None, None, Some(2.0), None]);
+        match search.run(2, 10) {
+            ConstrainedResult::Infeasible(dims) => assert_eq!(vec![2], dims),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn no_route() {
+        let graph = get_graph();
+        let mut search =
+            ConstrainedSearch::new(&graph, [1.0, 0.0, 0.0, 0.0], [None; EDGE_COST_DIMENSION]);
+        match search.run(0, 4) {
+            ConstrainedResult::NoRoute => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
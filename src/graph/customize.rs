@@ -0,0 +1,414 @@
+use std::cmp::Reverse;
+use std::collections::binary_heap::BinaryHeap;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+use log::info;
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+
+use crate::graph::edge::{Edge, HalfEdge};
+use crate::graph::Graph;
+use crate::helpers::{add_edge_costs, Costs};
+use crate::EDGE_COST_DIMENSION;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EdgeUpdate {
+    pub edge: usize,
+    pub costs: Costs,
+}
+
+impl Graph {
+    /// Sets the costs of original edges and recomputes the costs of all
+    /// shortcuts built from them, keeping the order of the hierarchy.
+    ///
+    /// Shortcuts that were left out during the contraction because of a witness
+    /// path are checked again, since the witness may have become more expensive.
+    /// Returns the number of shortcuts whose costs changed or that were added.
+    /// No edge is changed if any of the updates is invalid.
+    pub fn update_edge_costs(&mut self, updates: &[EdgeUpdate]) -> Result<usize, String> {
+        for update in updates {
+            match self.edges.get(update.edge) {
+                None => return Err(format!("Edge {} does not exist", update.edge)),
+                Some(edge) if edge.replaced_edges.is_some() => {
+                    return Err(format!(
+                        "Edge {} is a shortcut, its costs are derived from the edges it replaces",
+                        update.edge
+                    ))
+                }
+                Some(_) => (),
+            }
+            if update
+                .costs
+                .iter()
+                .any(|cost| !cost.is_finite() || *cost < 0.0)
+            {
+                return Err(format!(
+                    "Costs of edge {} must be finite and not negative",
+                    update.edge
+                ));
+            }
+        }
+
+        let mut changed = vec![false; self.edges.len()];
+        for update in updates {
+            self.set_edge_costs(update.edge, update.costs);
+            changed[update.edge] = true;
+        }
+
+        let mut num_of_shortcuts = 0;
+        for edge in self.get_shortcut_order() {
+            let (first, second) = self.edges[edge].replaced_edges.unwrap();
+            if changed[first] || changed[second] {
+                let costs =
+                    add_edge_costs(self.edges[first].edge_costs, self.edges[second].edge_costs);
+                self.set_edge_costs(edge, costs);
+                changed[edge] = true;
+                num_of_shortcuts += 1;
+            }
+        }
+        let num_of_added = if updates.is_empty() {
+            0
+        } else {
+            self.add_missing_shortcuts()
+        };
+        info!(
+            "Updated {} edges and {} shortcuts, added {} shortcuts",
+            updates.len(),
+            num_of_shortcuts,
+            num_of_added
+        );
+        Ok(num_of_shortcuts + num_of_added)
+    }
+
+    /// Contracts the nodes again in the order of their levels and adds the
+    /// shortcuts between higher neighbors that have no witness. A witness is a
+    /// path through higher nodes that costs at most as much in every dimension,
+    /// so the hierarchy stays correct for every alpha. Returns the number of
+    /// added shortcuts.
+    fn add_missing_shortcuts(&mut self) -> usize {
+        let levels: Vec<usize> = self.nodes.iter().map(|node| node.ch_level).collect();
+        let mut edges_out: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        let mut edges_in: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        let mut shortcuts = HashSet::new();
+        for edge in &self.edges {
+            edges_out[edge.source_id].push(edge.id);
+            edges_in[edge.target_id].push(edge.id);
+            if let Some(replaced_edges) = edge.replaced_edges {
+                shortcuts.insert(replaced_edges);
+            }
+        }
+
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+        order.sort_by_key(|id| (levels[*id], *id));
+        let num_of_edges = self.edges.len();
+        for node_id in order {
+            let level = levels[node_id];
+            let incoming: Vec<usize> = edges_in[node_id]
+                .iter()
+                .cloned()
+                .filter(|edge| levels[self.edges[*edge].source_id] > level)
+                .collect();
+            let outgoing: Vec<usize> = edges_out[node_id]
+                .iter()
+                .cloned()
+                .filter(|edge| levels[self.edges[*edge].target_id] > level)
+                .collect();
+            for edge_in in incoming {
+                let source_id = self.edges[edge_in].source_id;
+                let candidates: Vec<(usize, Costs)> = outgoing
+                    .iter()
+                    .filter(|edge_out| {
+                        self.edges[**edge_out].target_id != source_id
+                            && !shortcuts.contains(&(edge_in, **edge_out))
+                    })
+                    .map(|edge_out| {
+                        let costs = add_edge_costs(
+                            self.edges[edge_in].edge_costs,
+                            self.edges[*edge_out].edge_costs,
+                        );
+                        (*edge_out, costs)
+                    })
+                    .collect();
+                if candidates.is_empty() {
+                    continue;
+                }
+                let max_cost = candidates
+                    .iter()
+                    .map(|(_, costs)| costs.iter().sum::<f64>())
+                    .fold(0.0, f64::max);
+                let witnesses =
+                    witness_search(&self.edges, &edges_out, &levels, level, source_id, max_cost);
+                for (edge_out, costs) in candidates {
+                    let target_id = self.edges[edge_out].target_id;
+                    let dominated = witnesses.get(&target_id).is_some_and(|witness| {
+                        witness.iter().zip(costs.iter()).all(|(w, c)| w <= c)
+                    });
+                    if dominated {
+                        continue;
+                    }
+                    let id = self.edges.len();
+                    self.edges.push(Edge::new(
+                        id,
+                        source_id,
+                        target_id,
+                        costs,
+                        Some((edge_in, edge_out)),
+                    ));
+                    edges_out[source_id].push(id);
+                    edges_in[target_id].push(id);
+                    shortcuts.insert((edge_in, edge_out));
+                }
+            }
+        }
+
+        let num_of_added = self.edges.len() - num_of_edges;
+        if num_of_added > 0 {
+            self.rebuild_adjacency();
+        }
+        num_of_added
+    }
+
+    /// Builds the half edges again after edges were added
+    fn rebuild_adjacency(&mut self) {
+        let graph = Graph::new(mem::take(&mut self.nodes), mem::take(&mut self.edges));
+        self.nodes = graph.nodes;
+        self.edges = graph.edges;
+        self.offsets_in = graph.offsets_in;
+        self.offsets_out = graph.offsets_out;
+        self.half_edges_in = graph.half_edges_in;
+        self.half_edges_out = graph.half_edges_out;
+        self.offsets_orig_in = graph.offsets_orig_in;
+        self.offsets_orig_out = graph.offsets_orig_out;
+        self.half_edges_orig_in = graph.half_edges_orig_in;
+        self.half_edges_orig_out = graph.half_edges_orig_out;
+        if self.unpack_table.is_some() {
+            self.build_unpack_table();
+        }
+    }
+
+    /// All shortcuts, each one after the edges it replaces
+    fn get_shortcut_order(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.edges.len()];
+        let mut order = Vec::new();
+        for edge in &self.edges {
+            if edge.replaced_edges.is_none() || visited[edge.id] {
+                continue;
+            }
+            // (edge, children done)
+            let mut stack = vec![(edge.id, false)];
+            while let Some((edge, children_done)) = stack.pop() {
+                match self.edges[edge].replaced_edges {
+                    Some(_) if visited[edge] => (),
+                    Some(_) if children_done => {
+                        visited[edge] = true;
+                        order.push(edge);
+                    }
+                    Some((first, second)) => {
+                        stack.push((edge, true));
+                        stack.push((second, false));
+                        stack.push((first, false));
+                    }
+                    None => (),
+                }
+            }
+        }
+        order
+    }
+
+    /// Changes the costs of the edge and all of its half edges
+    fn set_edge_costs(&mut self, edge_id: usize, costs: Costs) {
+        let edge = &mut self.edges[edge_id];
+        edge.edge_costs = costs;
+        let (source_id, target_id) = (edge.source_id, edge.target_id);
+
+        let ranges = [
+            (&self.offsets_out, &mut self.half_edges_out, source_id),
+            (&self.offsets_in, &mut self.half_edges_in, target_id),
+            (
+                &self.offsets_orig_out,
+                &mut self.half_edges_orig_out,
+                source_id,
+            ),
+            (
+                &self.offsets_orig_in,
+                &mut self.half_edges_orig_in,
+                target_id,
+            ),
+        ];
+        for (offsets, half_edges, node_id) in ranges {
+            set_half_edge_costs(
+                &mut half_edges[offsets[node_id]..offsets[node_id + 1]],
+                edge_id,
+                costs,
+            );
+        }
+    }
+}
+
+/// Costs of the paths from the source through nodes above the level, found by
+/// a search on the sum of the cost dimensions that stops after max_cost
+fn witness_search(
+    edges: &[Edge],
+    edges_out: &[Vec<usize>],
+    levels: &[usize],
+    level: usize,
+    source_id: usize,
+    max_cost: f64,
+) -> HashMap<usize, Costs> {
+    let mut best: HashMap<usize, (f64, Costs)> = HashMap::new();
+    let mut candidates = BinaryHeap::new();
+    best.insert(source_id, (0.0, [0.0; EDGE_COST_DIMENSION]));
+    candidates.push(Reverse((OrderedFloat(0.0), source_id)));
+    while let Some(Reverse((OrderedFloat(cost), node_id))) = candidates.pop() {
+        if cost > max_cost {
+            break;
+        }
+        let (best_cost, costs) = best[&node_id];
+        if cost > best_cost {
+            continue;
+        }
+        for edge in edges_out[node_id].iter().map(|edge| &edges[*edge]) {
+            if levels[edge.target_id] <= level {
+                continue;
+            }
+            let next_cost = cost + edge.edge_costs.iter().sum::<f64>();
+            let improves = best
+                .get(&edge.target_id)
+                .is_none_or(|(known, _)| next_cost < *known);
+            if improves {
+                let next_costs = add_edge_costs(costs, edge.edge_costs);
+                best.insert(edge.target_id, (next_cost, next_costs));
+                candidates.push(Reverse((OrderedFloat(next_cost), edge.target_id)));
+            }
+        }
+    }
+    best.into_iter()
+        .map(|(node_id, (_, costs))| (node_id, costs))
+        .collect()
+}
+
+fn set_half_edge_costs(half_edges: &mut [HalfEdge], edge_id: usize, costs: Costs) {
+    if let Some(half_edge) = half_edges
+        .iter_mut()
+        .find(|half_edge| half_edge.edge_id == edge_id)
+    {
+        half_edge.edge_costs = costs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::generator::generate_grid_graph;
+    use crate::graph::{parse_graph_file, QueryMode};
+
+    #[test]
+    fn shortcut_costs() {
+        let mut graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        // edge 12 is part of shortcut 19, which shortcuts 20 and 21 are built on
+        let update = EdgeUpdate {
+            edge: 12,
+            costs: [3.0, 3.0, 1.0, 1.0],
+        };
+        assert_eq!(Ok(3), graph.update_edge_costs(&[update]));
+        assert_eq!([4.0, 4.0, 2.0, 2.0], graph.edges[19].edge_costs);
+        assert_eq!([7.0, 7.0, 3.0, 3.0], graph.edges[20].edge_costs);
+        assert_eq!([5.0, 5.0, 3.0, 3.0], graph.edges[21].edge_costs);
+        let half_edge = graph
+            .get_ch_edges_out(2)
+            .iter()
+            .find(|half_edge| half_edge.edge_id == 20)
+            .unwrap();
+        assert_eq!([7.0, 7.0, 3.0, 3.0], half_edge.edge_costs);
+
+        let path = graph
+            .find_shortest_path(0, vec![2, 10], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        assert_eq!([6.0, 6.0, 4.0, 4.0], path.total_dimension_costs);
+    }
+
+    #[test]
+    fn raised_witness() {
+        let mut graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        let path = graph.find_shortest_path(0, vec![2, 6], alpha).unwrap();
+        assert_eq!(vec![4, 8], path.edges);
+        // edge 8 is 4 -> 6, the contraction left out shortcuts that it was a witness for
+        let update = EdgeUpdate {
+            edge: 8,
+            costs: [11.0, 11.0, 11.0, 11.0],
+        };
+        graph.update_edge_costs(&[update]).unwrap();
+        assert_eq!(QueryMode::Hierarchy, graph.query_mode);
+        let path = graph.find_shortest_path(0, vec![2, 6], alpha).unwrap();
+        assert_eq!(vec![3, 1, 6], path.edges);
+        assert_eq!([4.0, 4.0, 3.0, 3.0], path.total_dimension_costs);
+    }
+
+    #[test]
+    fn hierarchy_after_updates() {
+        let mut graph = generate_grid_graph(6, 6);
+        // costs that are no longer proportional, so witnesses depend on alpha
+        let updates: Vec<EdgeUpdate> = graph
+            .edges
+            .iter()
+            .filter(|edge| edge.replaced_edges.is_none() && edge.id % 3 == 0)
+            .map(|edge| EdgeUpdate {
+                edge: edge.id,
+                costs: [
+                    1.0 + (edge.id % 4) as f64,
+                    0.5 + (edge.id * 3 % 5) as f64,
+                    edge.edge_costs[2],
+                    (edge.id % 2) as f64,
+                ],
+            })
+            .collect();
+        graph.update_edge_costs(&updates).unwrap();
+        let mut plain = graph.clone();
+        plain.set_query_mode(QueryMode::Plain);
+        let alphas = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+            [0.25, 0.25, 0.25, 0.25],
+        ];
+        for alpha in alphas.iter() {
+            for source in (0..36).step_by(5) {
+                for target in (0..36).step_by(7) {
+                    let cost = |graph: &Graph| {
+                        let path = graph
+                            .find_shortest_path(0, vec![source, target], *alpha)
+                            .unwrap();
+                        path.user_split.get_total_cost()
+                    };
+                    let expected = cost(&plain);
+                    assert!(
+                        (expected - cost(&graph)).abs() < 1e-9,
+                        "{} -> {} for {:?}",
+                        source,
+                        target,
+                        alpha
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_updates() {
+        let mut graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        let updates = [
+            EdgeUpdate {
+                edge: 12,
+                costs: [3.0, 3.0, 1.0, 1.0],
+            },
+            EdgeUpdate {
+                edge: 19,
+                costs: [1.0, 1.0, 1.0, 1.0],
+            },
+        ];
+        assert!(graph.update_edge_costs(&updates).is_err());
+        assert_eq!([1.0, 1.0, 1.0, 1.0], graph.edges[12].edge_costs);
+    }
+}
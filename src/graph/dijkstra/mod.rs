@@ -1,10 +1,15 @@
 use std::collections::binary_heap::BinaryHeap;
+use std::time::Instant;
 
-use state::Direction::{BACKWARD, FORWARD};
+use log::{debug, warn};
+
+use state::Direction::{self, BACKWARD, FORWARD};
 use state::State;
 
-use crate::graph::Graph;
+use crate::graph::turns::TurnSearch;
+use crate::graph::{Graph, QueryMode};
 use crate::helpers::{add_edge_costs, costs_by_alpha, Costs, Preference};
+use crate::metrics::metrics;
 use crate::EDGE_COST_DIMENSION;
 
 mod state;
@@ -20,14 +25,35 @@ pub struct DijkstraResult {
     pub edges: Vec<usize>,
     pub costs: Costs,
     pub total_cost: f64,
+    pub stats: QueryStats,
+}
+
+// Size of the search space of a query
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueryStats {
+    pub nodes_popped: usize,
+    pub nodes_stalled: usize,
+    pub edges_relaxed: usize,
+}
+
+impl std::ops::AddAssign for QueryStats {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes_popped += other.nodes_popped;
+        self.nodes_stalled += other.nodes_stalled;
+        self.edges_relaxed += other.edges_relaxed;
+    }
 }
 
 struct Dijkstra<'a> {
     graph: &'a Graph,
+    mode: QueryMode,
+    // skip nodes that can be reached cheaper from a higher node (hierarchy mode only)
+    stall_on_demand: bool,
+    // edges that must not be used, indexed by edge id
+    blocked: Option<&'a [bool]>,
     candidates: BinaryHeap<State>,
     touched_nodes: Vec<usize>,
-    found_best_b: bool,
-    found_best_f: bool,
+    stats: QueryStats,
 
     // Best dist to/from node
     pub cost_f: Vec<(Costs, f64)>,
@@ -43,13 +69,28 @@ struct Dijkstra<'a> {
 
 impl<'a> Dijkstra<'a> {
     fn new(graph: &Graph) -> Dijkstra {
+        Self::with_mode(graph, graph.query_mode)
+    }
+
+    /// Blocked edges may have been witnesses that made shortcuts unnecessary during
+    /// the contraction, so the hierarchy can miss the best remaining path. Such
+    /// queries always use the plain search, which only relaxes original edges.
+    fn avoiding(graph: &'a Graph, blocked: &'a [bool]) -> Dijkstra<'a> {
+        let mut dijkstra = Self::with_mode(graph, QueryMode::Plain);
+        dijkstra.blocked = Some(blocked);
+        dijkstra
+    }
+
+    fn with_mode(graph: &Graph, mode: QueryMode) -> Dijkstra<'_> {
         let num_of_nodes = graph.nodes.len();
         Dijkstra {
             graph,
+            mode,
+            stall_on_demand: true,
+            blocked: None,
             candidates: BinaryHeap::new(),
             touched_nodes: Vec::new(),
-            found_best_b: false,
-            found_best_f: false,
+            stats: QueryStats::default(),
             cost_f: vec![([0.0; EDGE_COST_DIMENSION], std::f64::MAX); num_of_nodes],
             cost_b: vec![([0.0; EDGE_COST_DIMENSION], std::f64::MAX); num_of_nodes],
             previous_f: vec![None; num_of_nodes],
@@ -73,8 +114,7 @@ impl<'a> Dijkstra<'a> {
         }
         self.touched_nodes.clear();
 
-        self.found_best_b = false;
-        self.found_best_f = false;
+        self.stats = QueryStats::default();
 
         // Node states
         self.cost_f[source].1 = 0.0;
@@ -89,36 +129,29 @@ impl<'a> Dijkstra<'a> {
     fn run(&mut self, source: usize, target: usize, alpha: Preference) -> Option<DijkstraResult> {
         self.prepare(source, target);
 
-        // let now = Instant::now();
-        // let mut n_popped: usize = 0;
         while let Some(candidate) = self.candidates.pop() {
-            // n_popped += 1;
-            if self.found_best_f && self.found_best_b {
+            // The heap is shared by both directions, so no remaining candidate
+            // of either direction can lead to a cheaper path
+            if candidate.total_cost >= self.best_node.2 {
                 break;
             }
+            self.stats.nodes_popped += 1;
             self.process_state(candidate, alpha);
         }
 
         match self.best_node {
             (None, _, _) => None,
             (Some(node_id), costs, total_cost) => {
-                /*
-                println!(
-                    "Found path with cost {:?} in {:?}ms with {:?} nodes popped",
-                    total_cost,
-                    now.elapsed().as_millis(),
-                    n_popped
-                );
-                */
-                println!(
-                    "Found path with dim_costs {:?} and cost {:?}",
-                    costs, total_cost
+                debug!(
+                    "Found path with dim_costs {:?} and cost {:?} ({:?})",
+                    costs, total_cost, self.stats
                 );
                 let edges = self.make_edge_path(node_id);
                 Some(DijkstraResult {
                     edges,
                     costs,
                     total_cost,
+                    stats: self.stats,
                 })
             }
         }
@@ -132,29 +165,31 @@ impl<'a> Dijkstra<'a> {
             direction,
         } = candidate;
 
+        if self.mode == QueryMode::Hierarchy
+            && self.stall_on_demand
+            && self.is_stalled(node_id, total_cost, direction, alpha)
+        {
+            self.stats.nodes_stalled += 1;
+            return;
+        }
+
+        let blocked = self.blocked;
         let my_costs;
         let other_costs;
-        let found_best;
         let previous;
         if direction == FORWARD {
             my_costs = &mut self.cost_f;
             other_costs = &self.cost_b;
-            found_best = &mut self.found_best_f;
             previous = &mut self.previous_f;
         } else {
             my_costs = &mut self.cost_b;
             other_costs = &self.cost_f;
-            found_best = &mut self.found_best_b;
             previous = &mut self.previous_b;
         };
 
         if total_cost > my_costs[node_id].1 {
             return;
         };
-        if total_cost > self.best_node.2 {
-            *found_best = true;
-            return;
-        }
         if other_costs[node_id].1 != std::f64::MAX {
             let merged_cost = total_cost + other_costs[node_id].1;
             if merged_cost < self.best_node.2 {
@@ -163,12 +198,17 @@ impl<'a> Dijkstra<'a> {
             }
         }
 
-        let edges = if direction == FORWARD {
-            self.graph.get_ch_edges_out(node_id)
-        } else {
-            self.graph.get_ch_edges_in(node_id)
+        let edges = match (self.mode, direction) {
+            (QueryMode::Hierarchy, FORWARD) => self.graph.get_ch_edges_out(node_id),
+            (QueryMode::Hierarchy, BACKWARD) => self.graph.get_ch_edges_in(node_id),
+            (QueryMode::Plain, FORWARD) => self.graph.get_edges_out(node_id),
+            (QueryMode::Plain, BACKWARD) => self.graph.get_edges_in(node_id),
         };
         for half_edge in edges {
+            if blocked.is_some_and(|blocked| blocked[half_edge.edge_id]) {
+                continue;
+            }
+            self.stats.edges_relaxed += 1;
             let next_node = half_edge.target_id;
             let next_costs = add_edge_costs(costs, half_edge.edge_costs);
             let next_total_cost = total_cost + costs_by_alpha(half_edge.edge_costs, alpha);
@@ -187,6 +227,27 @@ impl<'a> Dijkstra<'a> {
         }
     }
 
+    /// A node is stalled if an edge from a higher node reaches it cheaper than its
+    /// current cost. Its cost can then not be the cost of an optimal path.
+    fn is_stalled(
+        &self,
+        node_id: usize,
+        total_cost: f64,
+        direction: Direction,
+        alpha: Preference,
+    ) -> bool {
+        let (edges, my_costs) = if direction == FORWARD {
+            (self.graph.get_ch_edges_in(node_id), &self.cost_f)
+        } else {
+            (self.graph.get_ch_edges_out(node_id), &self.cost_b)
+        };
+        edges.iter().any(|half_edge| {
+            let higher_cost = my_costs[half_edge.target_id].1;
+            higher_cost != std::f64::MAX
+                && higher_cost + costs_by_alpha(half_edge.edge_costs, alpha) < total_cost
+        })
+    }
+
     fn make_edge_path(&self, connector: usize) -> Vec<usize> {
         let mut edges = Vec::new();
         let mut previous_edge = self.previous_f[connector];
@@ -209,15 +270,138 @@ impl<'a> Dijkstra<'a> {
 }
 
 pub fn find_path(graph: &Graph, include: &[usize], alpha: Preference) -> Option<HalfPath> {
+    if graph.has_turn_rules() {
+        return find_turn_path(graph, include, alpha, None);
+    }
+    if graph.verify_queries {
+        for (leg, ch_cost, plain_cost) in find_mismatches(graph, include, alpha) {
+            warn!(
+                "Query mismatch from {} to {}: hierarchy cost {:?}, plain cost {:?}",
+                include[leg],
+                include[leg + 1],
+                ch_cost,
+                plain_cost
+            );
+        }
+    }
+    find_path_with_mode(graph, include, alpha, graph.query_mode)
+}
+
+/// Runs every leg of the route with both query modes and returns those that differ
+/// in their cost as (leg, hierarchy cost, plain cost). Missing routes have a cost of None.
+pub fn find_mismatches(
+    graph: &Graph,
+    include: &[usize],
+    alpha: Preference,
+) -> Vec<(usize, Option<f64>, Option<f64>)> {
+    let mut ch_dijkstra = Dijkstra::with_mode(graph, QueryMode::Hierarchy);
+    let mut plain_dijkstra = Dijkstra::with_mode(graph, QueryMode::Plain);
+    let mut mismatches = Vec::new();
+    let mut ch_stats = QueryStats::default();
+    let mut plain_stats = QueryStats::default();
+    for (leg, win) in include.windows(2).enumerate() {
+        let ch_result = ch_dijkstra.run(win[0], win[1], alpha);
+        let plain_result = plain_dijkstra.run(win[0], win[1], alpha);
+        if let Some(result) = &ch_result {
+            ch_stats += result.stats;
+        }
+        if let Some(result) = &plain_result {
+            plain_stats += result.stats;
+        }
+        let ch_cost = ch_result.map(|result| result.total_cost);
+        let plain_cost = plain_result.map(|result| result.total_cost);
+        if !total_costs_equal(ch_cost, plain_cost) {
+            mismatches.push((leg, ch_cost, plain_cost));
+        }
+    }
+    debug!(
+        "Verified query, hierarchy {:?}, plain {:?}",
+        ch_stats, plain_stats
+    );
+    mismatches
+}
+
+/// Compares query results up to rounding errors of the summation order
+fn total_costs_equal(a: Option<f64>, b: Option<f64>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Like find_path, but never uses the blocked edges
+pub fn find_path_avoiding(
+    graph: &Graph,
+    include: &[usize],
+    alpha: Preference,
+    blocked: &[bool],
+) -> Option<HalfPath> {
+    if graph.has_turn_rules() {
+        return find_turn_path(graph, include, alpha, Some(blocked));
+    }
+    let mut dijkstra = Dijkstra::avoiding(graph, blocked);
+    run_legs(include, |source, target| {
+        dijkstra.run(source, target, alpha)
+    })
+}
+
+/// Every leg continues on the edge the previous one arrived on, so turns at the
+/// waypoints follow the turn rules as well
+fn find_turn_path(
+    graph: &Graph,
+    include: &[usize],
+    alpha: Preference,
+    blocked: Option<&[bool]>,
+) -> Option<HalfPath> {
+    let mut search = TurnSearch::new(graph, blocked);
+    let mut incoming = None;
+    run_legs(include, |source, target| {
+        let result = search.run_after(incoming, source, target, alpha)?;
+        if let Some(last_edge) = result.edges.last() {
+            incoming = Some(*last_edge);
+        }
+        Some(result)
+    })
+}
+
+fn find_path_with_mode(
+    graph: &Graph,
+    include: &[usize],
+    alpha: Preference,
+    mode: QueryMode,
+) -> Option<HalfPath> {
+    let mut dijkstra = Dijkstra::with_mode(graph, mode);
+    run_legs(include, |source, target| {
+        dijkstra.run(source, target, alpha)
+    })
+}
+
+fn run_legs<F>(include: &[usize], mut run: F) -> Option<HalfPath>
+where
+    F: FnMut(usize, usize) -> Option<DijkstraResult>,
+{
     // println!("=== Running Dijkstra search ===");
-    let mut dijkstra = Dijkstra::new(graph);
     let mut edges = Vec::new();
     let mut dimension_costs = Vec::new();
     let mut total_dimension_costs = [0.0; EDGE_COST_DIMENSION];
     let mut costs_by_alpha = Vec::new();
 
+    let metrics = metrics();
     for win in include.windows(2) {
-        if let Some(result) = dijkstra.run(win[0], win[1], alpha) {
+        let started = Instant::now();
+        let result = run(win[0], win[1]);
+        metrics.queries.inc();
+        metrics
+            .query_seconds
+            .observe(started.elapsed().as_secs_f64());
+        if let Some(result) = result {
+            metrics
+                .query_nodes_popped
+                .observe(result.stats.nodes_popped as f64);
+            metrics
+                .query_edges_relaxed
+                .observe(result.stats.edges_relaxed as f64);
             edges.push(result.edges);
             result
                 .costs
@@ -227,7 +411,8 @@ pub fn find_path(graph: &Graph, include: &[usize], alpha: Preference) -> Option<
             dimension_costs.push(result.costs);
             costs_by_alpha.push(result.total_cost);
         } else {
-            println!("=== Dijkstra could not find a route ===");
+            metrics.queries_unreachable.inc();
+            debug!("Dijkstra could not find a route");
             return None;
         }
     }
@@ -240,8 +425,45 @@ pub fn find_path(graph: &Graph, include: &[usize], alpha: Preference) -> Option<
     })
 }
 
+pub fn find_cost_matrix(
+    graph: &Graph,
+    nodes: &[usize],
+    alpha: Preference,
+    blocked: Option<&[bool]>,
+) -> Vec<Vec<f64>> {
+    let mut run: Box<dyn FnMut(usize, usize) -> Option<DijkstraResult>> =
+        match (graph.has_turn_rules(), blocked) {
+            (true, blocked) => {
+                let mut search = TurnSearch::new(graph, blocked);
+                Box::new(move |source, target| search.run(source, target, alpha))
+            }
+            (false, Some(blocked)) => {
+                let mut dijkstra = Dijkstra::avoiding(graph, blocked);
+                Box::new(move |source, target| dijkstra.run(source, target, alpha))
+            }
+            (false, None) => {
+                let mut dijkstra = Dijkstra::new(graph);
+                Box::new(move |source, target| dijkstra.run(source, target, alpha))
+            }
+        };
+    let mut costs = vec![vec![0.0; nodes.len()]; nodes.len()];
+    for (source_idx, source) in nodes.iter().enumerate() {
+        for (target_idx, target) in nodes.iter().enumerate() {
+            if source == target {
+                continue;
+            }
+            costs[source_idx][target_idx] = match run(*source, *target) {
+                Some(result) => result.total_cost,
+                None => std::f64::MAX,
+            };
+        }
+    }
+    costs
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::graph::generator::generate_grid_graph;
     use crate::graph::{parse_graph_file, Graph};
 
     use super::*;
@@ -326,4 +548,71 @@ mod tests {
         assert_eq!(path_conc.edges, vec![21]);
         assert_eq!(path_conc.total_cost, 3.0);
     }
+
+    #[test]
+    fn plain_mode() {
+        let conc_graph = get_conc_graph();
+        let mut dijkstra = Dijkstra::with_mode(&conc_graph, QueryMode::Plain);
+        let alpha = [0.0, 1.0, 0.0, 0.0];
+
+        assert!(dijkstra.run(0, 4, alpha).is_none());
+
+        let path = dijkstra.run(2, 10, alpha).unwrap();
+        assert_eq!(path.edges, vec![4, 7, 9, 12]);
+        assert_eq!(path.total_cost, 4.0);
+
+        let path = dijkstra.run(4, 10, alpha).unwrap();
+        assert_eq!(path.edges, vec![7, 9, 12]);
+        assert_eq!(path.total_cost, 3.0);
+    }
+
+    #[test]
+    fn stall_on_demand() {
+        let alpha = [0.4, 0.1, 0.3, 0.2];
+        for graph in &[
+            get_conc_graph(),
+            generate_grid_graph(12, 12),
+            generate_grid_graph(40, 40),
+        ] {
+            let mut stalling = Dijkstra::with_mode(graph, QueryMode::Hierarchy);
+            let mut not_stalling = Dijkstra::with_mode(graph, QueryMode::Hierarchy);
+            not_stalling.stall_on_demand = false;
+            let mut plain = Dijkstra::with_mode(graph, QueryMode::Plain);
+            let mut stats = [QueryStats::default(); 3];
+
+            let num_of_nodes = graph.nodes.len();
+            let step = 1 + num_of_nodes / 100;
+            for source in (0..num_of_nodes).step_by(5 * step) {
+                for target in (0..num_of_nodes).step_by(7 * step) {
+                    let results = [
+                        stalling.run(source, target, alpha),
+                        not_stalling.run(source, target, alpha),
+                        plain.run(source, target, alpha),
+                    ];
+                    let costs: Vec<Option<f64>> = results
+                        .iter()
+                        .map(|result| result.as_ref().map(|result| result.total_cost))
+                        .collect();
+                    assert!(total_costs_equal(costs[0], costs[2]));
+                    assert!(total_costs_equal(costs[1], costs[2]));
+                    for (index, result) in results.iter().enumerate() {
+                        if let Some(result) = result {
+                            stats[index] += result.stats;
+                        }
+                    }
+                }
+            }
+            assert!(stats[0].nodes_popped <= stats[1].nodes_popped);
+            assert!(stats[0].edges_relaxed <= stats[1].edges_relaxed);
+        }
+    }
+
+    #[test]
+    fn no_mismatches() {
+        let alpha = [0.2, 0.3, 0.4, 0.1];
+        let include: Vec<usize> = (0..12).collect();
+        for graph in &[get_graph(), get_conc_graph()] {
+            assert!(find_mismatches(graph, &include, alpha).is_empty());
+        }
+    }
 }
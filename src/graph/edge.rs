@@ -2,15 +2,25 @@ use crate::helpers::Costs;
 
 use super::EDGE_COST_DIMENSION;
 
-pub fn parse_costs(tokens: &[&str]) -> Costs {
+/// Parses one cost for each dimension
+pub fn parse_costs(tokens: &[&str]) -> Result<Costs, String> {
+    if tokens.len() != EDGE_COST_DIMENSION {
+        return Err(format!(
+            "Expected {} costs instead of {}",
+            EDGE_COST_DIMENSION,
+            tokens.len()
+        ));
+    }
     let mut edge_costs: Costs = [0.0; EDGE_COST_DIMENSION];
-    for (index, token) in tokens.iter().enumerate() {
-        edge_costs[index] = token.parse().unwrap();
+    for (cost, token) in edge_costs.iter_mut().zip(tokens) {
+        *cost = token
+            .parse()
+            .map_err(|_| format!("Invalid cost {}", token))?;
     }
-    edge_costs
+    Ok(edge_costs)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Edge {
     pub id: usize,
     pub source_id: usize,
@@ -37,7 +47,7 @@ impl Edge {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct HalfEdge {
     pub edge_id: usize,
     pub target_id: usize,
@@ -0,0 +1,165 @@
+use std::cmp::Reverse;
+use std::collections::binary_heap::BinaryHeap;
+
+use ordered_float::OrderedFloat;
+
+use crate::graph::edge::Edge;
+use crate::graph::node::Node;
+use crate::graph::Graph;
+use crate::helpers::add_edge_costs;
+
+/// Generates a bidirected grid graph and contracts it, nodes on coarser grid
+/// lines last. All cost dimensions of an edge are proportional to its length,
+/// so a single witness search decides about a shortcut for every alpha.
+pub fn generate_grid_graph(width: usize, height: usize) -> Graph {
+    let num_of_nodes = width * height;
+    let mut edges: Vec<Edge> = Vec::new();
+    let mut edges_out: Vec<Vec<usize>> = vec![Vec::new(); num_of_nodes];
+    let mut edges_in: Vec<Vec<usize>> = vec![Vec::new(); num_of_nodes];
+    for y in 0..height {
+        for x in 0..width {
+            let id = y * width + x;
+            let length = 1.0 + ((x * 7 + y * 13) % 5) as f64;
+            if x + 1 < width {
+                add_edge(
+                    &mut edges,
+                    &mut edges_out,
+                    &mut edges_in,
+                    id,
+                    id + 1,
+                    length,
+                    None,
+                );
+                add_edge(
+                    &mut edges,
+                    &mut edges_out,
+                    &mut edges_in,
+                    id + 1,
+                    id,
+                    length,
+                    None,
+                );
+            }
+            if y + 1 < height {
+                add_edge(
+                    &mut edges,
+                    &mut edges_out,
+                    &mut edges_in,
+                    id,
+                    id + width,
+                    length,
+                    None,
+                );
+                add_edge(
+                    &mut edges,
+                    &mut edges_out,
+                    &mut edges_in,
+                    id + width,
+                    id,
+                    length,
+                    None,
+                );
+            }
+        }
+    }
+
+    let importance = |id: usize| {
+        let (x, y) = ((id % width + 1) as u32, (id / width + 1) as u32);
+        x.trailing_zeros().min(y.trailing_zeros()) * 32 + x.trailing_zeros().max(y.trailing_zeros())
+    };
+    let mut order: Vec<usize> = (0..num_of_nodes).collect();
+    order.sort_by_key(|id| (importance(*id), *id));
+
+    let mut ch_level = vec![0; num_of_nodes];
+    let mut contracted = vec![false; num_of_nodes];
+    for (level, node_id) in order.into_iter().enumerate() {
+        ch_level[node_id] = level;
+        contracted[node_id] = true;
+        let incoming: Vec<usize> = edges_in[node_id]
+            .iter()
+            .cloned()
+            .filter(|edge| !contracted[edges[*edge].source_id])
+            .collect();
+        let outgoing: Vec<usize> = edges_out[node_id]
+            .iter()
+            .cloned()
+            .filter(|edge| !contracted[edges[*edge].target_id])
+            .collect();
+        for edge_in in &incoming {
+            let source_id = edges[*edge_in].source_id;
+            let witness = witness_search(&edges, &edges_out, &contracted, source_id);
+            for edge_out in &outgoing {
+                let target_id = edges[*edge_out].target_id;
+                let costs = add_edge_costs(edges[*edge_in].edge_costs, edges[*edge_out].edge_costs);
+                if source_id == target_id || witness[target_id] <= costs[0] {
+                    continue;
+                }
+                add_edge(
+                    &mut edges,
+                    &mut edges_out,
+                    &mut edges_in,
+                    source_id,
+                    target_id,
+                    costs[0],
+                    Some((*edge_in, *edge_out)),
+                );
+            }
+        }
+    }
+
+    let nodes = (0..num_of_nodes)
+        .map(|id| {
+            Node::new(
+                id,
+                (id / width) as f64,
+                (id % width) as f64,
+                0.0,
+                ch_level[id],
+            )
+        })
+        .collect();
+    Graph::new(nodes, edges)
+}
+
+fn add_edge(
+    edges: &mut Vec<Edge>,
+    edges_out: &mut [Vec<usize>],
+    edges_in: &mut [Vec<usize>],
+    source_id: usize,
+    target_id: usize,
+    length: f64,
+    replaced_edges: Option<(usize, usize)>,
+) {
+    let id = edges.len();
+    let costs = [length, length, 0.5 * length, 2.0 * length];
+    edges.push(Edge::new(id, source_id, target_id, costs, replaced_edges));
+    edges_out[source_id].push(id);
+    edges_in[target_id].push(id);
+}
+
+/// Lengths of the shortest paths from the source through uncontracted nodes
+fn witness_search(
+    edges: &[Edge],
+    edges_out: &[Vec<usize>],
+    contracted: &[bool],
+    source_id: usize,
+) -> Vec<f64> {
+    let mut lengths = vec![std::f64::MAX; contracted.len()];
+    let mut candidates = BinaryHeap::new();
+    lengths[source_id] = 0.0;
+    candidates.push((Reverse(OrderedFloat(0.0)), source_id));
+    while let Some((Reverse(OrderedFloat(length)), node_id)) = candidates.pop() {
+        if length > lengths[node_id] {
+            continue;
+        }
+        for edge in &edges_out[node_id] {
+            let target_id = edges[*edge].target_id;
+            let next_length = length + edges[*edge].edge_costs[0];
+            if !contracted[target_id] && next_length < lengths[target_id] {
+                lengths[target_id] = next_length;
+                candidates.push((Reverse(OrderedFloat(next_length)), target_id));
+            }
+        }
+    }
+    lengths
+}
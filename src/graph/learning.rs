@@ -0,0 +1,26 @@
+use crate::helpers::Preference;
+
+/// Follows a run of Graph::find_preference_observed. Positions are indices
+/// into the nodes of the path.
+pub trait LearningObserver {
+    /// The search tries whether one alpha explains the part from start to end
+    fn probe_started(&mut self, _start: usize, _end: usize) {}
+
+    /// The LP found the alpha to try next, None if no alpha is left
+    fn lp_solved(&mut self, _alpha: Option<&Preference>) {}
+
+    /// The alpha that explains the part from start to end, if there is one
+    fn probe_finished(&mut self, _start: usize, _end: usize, _alpha: Option<&Preference>) {}
+
+    /// The part from start to cut is optimal for the alpha, the next part
+    /// starts at cut
+    fn segment_found(&mut self, _start: usize, _cut: usize, _alpha: &Preference) {}
+
+    /// Checked before every probe, the search stops if it returns true
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+// for runs nobody follows
+impl LearningObserver for () {}
@@ -1,38 +1,108 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::time::Instant;
 
-use edge::{Edge, HalfEdge};
-use node::Node;
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use avoid::Avoid;
+use dijkstra::HalfPath;
+use edge::HalfEdge;
+use learning::LearningObserver;
 use path::Path;
 
+use crate::config::{get_config, AppConfig};
+use crate::graph::constrained::{Budgets, ConstrainedResult, ConstrainedSearch};
 use crate::graph::path::PathSplit;
-use crate::helpers::{Coordinate, Preference};
+use crate::helpers::{BoundingBox, Coordinate, Costs, Preference};
+use crate::lp::partition::{PreferencePartition, PreferenceRegion};
+use crate::lp::sensitivity::{PreferenceSensitivity, SensitivityAnalysis};
 use crate::lp::PreferenceEstimator;
+use crate::metrics::metrics;
 use crate::EDGE_COST_DIMENSION;
 
+pub use edge::Edge;
+pub use node::Node;
+
+pub mod avoid;
+pub mod constrained;
+pub mod customize;
 mod dijkstra;
 mod edge;
+pub mod generator;
+pub mod learning;
 mod node;
 pub mod path;
+mod tour;
+mod turns;
+mod validate;
+
+// Which edges the Dijkstra queries run on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryMode {
+    // bidirectional search on the upward and downward edges of the contraction hierarchy
+    #[default]
+    Hierarchy,
+    // bidirectional search on all original edges, also works for graphs without hierarchy
+    Plain,
+}
+
+// Unpacked original edges of all shortcuts, original edges have empty ranges
+#[derive(Clone, Debug)]
+struct UnpackTable {
+    offsets: Vec<usize>,
+    edges: Vec<usize>,
+}
 
-#[derive(Debug)]
+/// Road network with a contraction hierarchy. Nodes and edges are indexed by
+/// their ids, shortcuts are edges that replace two others.
+#[derive(Clone, Debug)]
 pub struct Graph {
+    // graphs of different regions are told apart by their names
+    name: String,
+    // file the graph was loaded from, empty if it was built in memory
+    file_path: String,
+    // identifies the original nodes and edges, see calc_fingerprint
+    fingerprint: String,
+    bounding_box: BoundingBox,
     pub nodes: Vec<Node>,
     pub edges: Vec<Edge>,
     offsets_in: Vec<usize>,
     offsets_out: Vec<usize>,
     half_edges_in: Vec<HalfEdge>,
     half_edges_out: Vec<HalfEdge>,
+    // adjacency of the original edges, without shortcuts
+    offsets_orig_in: Vec<usize>,
+    offsets_orig_out: Vec<usize>,
+    half_edges_orig_in: Vec<HalfEdge>,
+    half_edges_orig_out: Vec<HalfEdge>,
+    query_mode: QueryMode,
+    // run every query in both modes and report differing costs
+    verify_queries: bool,
+    unpack_table: Option<UnpackTable>,
+    // costs of turning from one original edge into the next, None if restricted
+    turns: HashMap<(usize, usize), Option<Costs>>,
+    forbid_u_turns: bool,
 }
 
 impl Graph {
     fn new(mut nodes: Vec<Node>, mut edges: Vec<Edge>) -> Graph {
-        println!("Constructing graph...");
+        debug!(
+            "Constructing graph with {} nodes and {} edges",
+            nodes.len(),
+            edges.len()
+        );
         let mut offsets_out: Vec<usize> = vec![0; nodes.len() + 1];
         let mut offsets_in: Vec<usize> = vec![0; nodes.len() + 1];
         let mut half_edges_out: Vec<HalfEdge> = Vec::new();
         let mut half_edges_in: Vec<HalfEdge> = Vec::new();
+        let mut offsets_orig_out: Vec<usize> = vec![0; nodes.len() + 1];
+        let mut offsets_orig_in: Vec<usize> = vec![0; nodes.len() + 1];
+        let mut half_edges_orig_out: Vec<HalfEdge> = Vec::new();
+        let mut half_edges_orig_in: Vec<HalfEdge> = Vec::new();
 
         // sort nodes by id
         nodes.sort_by(|a, b| a.id.cmp(&b.id));
@@ -46,6 +116,13 @@ impl Graph {
                 offsets_out[edge.source_id + 1] += 1;
                 half_edges_out.push(HalfEdge::new(edge.id, edge.target_id, edge.edge_costs));
             });
+        edges
+            .iter()
+            .filter(|edge| edge.replaced_edges.is_none())
+            .for_each(|edge| {
+                offsets_orig_out[edge.source_id + 1] += 1;
+                half_edges_orig_out.push(HalfEdge::new(edge.id, edge.target_id, edge.edge_costs));
+            });
 
         // half_edges and offsets in
         edges.sort_by(|a, b| a.target_id.cmp(&b.target_id));
@@ -56,25 +133,89 @@ impl Graph {
                 offsets_in[edge.target_id + 1] += 1;
                 half_edges_in.push(HalfEdge::new(edge.id, edge.source_id, edge.edge_costs));
             });
+        edges
+            .iter()
+            .filter(|edge| edge.replaced_edges.is_none())
+            .for_each(|edge| {
+                offsets_orig_in[edge.target_id + 1] += 1;
+                half_edges_orig_in.push(HalfEdge::new(edge.id, edge.source_id, edge.edge_costs));
+            });
 
         // finish offset arrays
         for index in 1..offsets_out.len() {
             offsets_out[index] += offsets_out[index - 1];
             offsets_in[index] += offsets_in[index - 1];
+            offsets_orig_out[index] += offsets_orig_out[index - 1];
+            offsets_orig_in[index] += offsets_orig_in[index - 1];
         }
 
         // sort edges by id
         edges.sort_by(|a, b| a.id.cmp(&b.id));
+        let bounding_box = BoundingBox::around(nodes.iter().map(|node| &node.location));
+        let fingerprint = calc_fingerprint(&nodes, &edges);
         Graph {
+            name: String::from("default"),
+            file_path: String::new(),
+            fingerprint,
+            bounding_box,
             nodes,
             edges,
             offsets_in,
             offsets_out,
             half_edges_in,
             half_edges_out,
+            offsets_orig_in,
+            offsets_orig_out,
+            half_edges_orig_in,
+            half_edges_orig_out,
+            query_mode: QueryMode::default(),
+            verify_queries: false,
+            unpack_table: None,
+            turns: HashMap::new(),
+            forbid_u_turns: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    pub fn bounding_box(&self) -> &BoundingBox {
+        &self.bounding_box
+    }
+
+    pub fn set_query_mode(&mut self, query_mode: QueryMode) {
+        self.query_mode = query_mode;
+    }
+
+    pub fn set_verify_queries(&mut self, verify_queries: bool) {
+        self.verify_queries = verify_queries;
+    }
+
+    /// Applies the query and turn settings of the config
+    pub fn configure(&mut self, config: &AppConfig) {
+        self.set_query_mode(config.query_mode());
+        self.set_verify_queries(config.verify_queries());
+        self.set_forbid_u_turns(config.forbid_u_turns());
+        if config.precompute_unpacking() {
+            self.build_unpack_table();
         }
     }
 
+    /// Like find_shortest_path, but the waypoints are the nodes closest to the
+    /// coordinates
     pub fn find_shortest_path_alt(
         &self,
         id: usize,
@@ -88,69 +229,187 @@ impl Graph {
         self.find_shortest_path(id, include, alpha)
     }
 
+    pub fn find_shortest_path_avoiding_alt(
+        &self,
+        id: usize,
+        include: Vec<Coordinate>,
+        alpha: Preference,
+        avoid: &Avoid,
+    ) -> Option<Path> {
+        let include = include
+            .iter()
+            .map(|x| self.find_closest_node(x).id)
+            .collect();
+        self.find_shortest_path_avoiding(id, include, alpha, avoid)
+    }
+
+    pub fn find_shortest_path_optimized_alt(
+        &self,
+        id: usize,
+        include: Vec<Coordinate>,
+        alpha: Preference,
+        avoid: &Avoid,
+    ) -> Option<Path> {
+        let include = include
+            .iter()
+            .map(|x| self.find_closest_node(x).id)
+            .collect();
+        self.find_shortest_path_optimized(id, include, alpha, avoid)
+    }
+
+    /// Like find_shortest_path, but visits the intermediate waypoints in the order
+    /// with the lowest total cost. The indices of the waypoints in that order are
+    /// stored in the path.
+    pub fn find_shortest_path_optimized(
+        &self,
+        id: usize,
+        include: Vec<usize>,
+        alpha: Preference,
+        avoid: &Avoid,
+    ) -> Option<Path> {
+        let blocked = avoid.find_blocked_edges(self);
+        let costs = dijkstra::find_cost_matrix(self, &include, alpha, blocked.as_deref());
+        let order = tour::find_order(&costs)?;
+        let include: Vec<usize> = order.iter().map(|index| include[*index]).collect();
+        let result = match &blocked {
+            Some(blocked) => dijkstra::find_path_avoiding(self, &include, alpha, blocked),
+            None => dijkstra::find_path(self, &include, alpha),
+        };
+        let result = result?;
+        let mut path = self.make_path(id, &include, result, alpha)?;
+        path.order = Some(order);
+        path.avoid = avoid.clone();
+        Some(path)
+    }
+
+    /// Cheapest route through the waypoint nodes in their order for the alpha,
+    /// or None if there are no waypoints or one can not be reached. The id is stored in the path.
     pub fn find_shortest_path(
         &self,
         id: usize,
         include: Vec<usize>,
         alpha: Preference,
     ) -> Option<Path> {
-        if let Some(result) = dijkstra::find_path(self, &include, alpha) {
-            let unpacked_edges: Vec<Vec<usize>> = result
-                .edges
-                .iter()
-                .map(|subpath_edges| {
-                    subpath_edges
-                        .iter()
-                        .flat_map(|edge| self.unpack_edge(*edge))
-                        .collect()
-                })
-                .collect();
-            let cuts = unpacked_edges.iter().map(|edges| edges.len()).collect();
-
-            let edges: Vec<usize> = unpacked_edges.into_iter().flatten().collect();
-            let mut nodes: Vec<usize> = edges
-                .iter()
-                .map(|edge| self.edges[*edge].source_id)
-                .collect();
-            nodes.push(*include.last().unwrap());
-
-            let coordinates = nodes.iter().map(|id| self.nodes[*id].location).collect();
-            let waypoints = include.iter().map(|id| self.nodes[*id].location).collect();
-
-            return Some(Path {
-                id,
-                nodes,
-                edges,
-                coordinates,
-                waypoints,
-                user_split: PathSplit {
-                    cuts,
-                    alphas: vec![alpha],
-                    dimension_costs: result.dimension_costs,
-                    costs_by_alpha: result.costs_by_alpha,
-                },
-                algo_split: None,
-                total_dimension_costs: result.total_dimension_costs,
-            });
+        dijkstra::find_path(self, &include, alpha)
+            .and_then(|result| self.make_path(id, &include, result, alpha))
+    }
+
+    /// Like find_shortest_path, but the route does not use any of the avoided
+    /// edges, nodes or areas. The path keeps what it avoided.
+    pub fn find_shortest_path_avoiding(
+        &self,
+        id: usize,
+        include: Vec<usize>,
+        alpha: Preference,
+        avoid: &Avoid,
+    ) -> Option<Path> {
+        let mut path = match avoid.find_blocked_edges(self) {
+            Some(blocked) => dijkstra::find_path_avoiding(self, &include, alpha, &blocked)
+                .and_then(|result| self.make_path(id, &include, result, alpha)),
+            None => self.find_shortest_path(id, include, alpha),
+        }?;
+        path.avoid = avoid.clone();
+        Some(path)
+    }
+
+    /// Cheapest route for the alpha whose costs stay within the budgets
+    pub fn find_constrained_path(
+        &self,
+        source: usize,
+        target: usize,
+        alpha: Preference,
+        budgets: Budgets,
+    ) -> ConstrainedResult {
+        let mut search = ConstrainedSearch::new(self, alpha, budgets);
+        search.run(source, target)
+    }
+
+    // None if there are no waypoints to end the path at
+    fn make_path(
+        &self,
+        id: usize,
+        include: &[usize],
+        result: HalfPath,
+        alpha: Preference,
+    ) -> Option<Path> {
+        let mut edges: Vec<usize> = Vec::new();
+        let mut cuts = Vec::new();
+        for subpath_edges in &result.edges {
+            let subpath_start = edges.len();
+            for edge in subpath_edges {
+                self.unpack_edge(*edge, &mut edges);
+            }
+            cuts.push(edges.len() - subpath_start);
         }
-        None
+
+        let mut nodes: Vec<usize> = edges
+            .iter()
+            .map(|edge| self.edges[*edge].source_id)
+            .collect();
+        nodes.push(*include.last()?);
+
+        let coordinates = nodes.iter().map(|id| self.nodes[*id].location).collect();
+        let waypoints = include.iter().map(|id| self.nodes[*id].location).collect();
+
+        Some(Path {
+            id,
+            graph: self.name.clone(),
+            fingerprint: self.fingerprint.clone(),
+            stale: false,
+            nodes,
+            edges,
+            coordinates,
+            waypoints,
+            order: None,
+            avoid: Avoid::default(),
+            user_split: PathSplit {
+                cuts,
+                alphas: vec![alpha],
+                dimension_costs: result.dimension_costs,
+                costs_by_alpha: result.costs_by_alpha,
+            },
+            algo_split: None,
+            total_dimension_costs: result.total_dimension_costs,
+        })
     }
 
+    /// Splits the path into as few parts as possible, each of which is optimal
+    /// for one alpha, and stores them as the algo_split of the path. Needs a
+    /// GLPK installation.
     pub fn find_preference(&self, path: &mut Path) {
-        println!("=== Calculate Preference ===");
+        self.find_preference_observed(path, &mut ());
+    }
+
+    /// Like find_preference, but reports the found parts to the observer. Returns
+    /// false if the observer cancelled the search, the path is unchanged then.
+    pub fn find_preference_observed(
+        &self,
+        path: &mut Path,
+        observer: &mut dyn LearningObserver,
+    ) -> bool {
+        info!("Calculating preference of path {}", path.id);
+        let metrics = metrics();
+        metrics.learning_runs.inc();
+        let started = Instant::now();
         let path_length = path.nodes.len();
         let mut cuts = Vec::new();
         let mut alphas = Vec::new();
         let mut start: usize = 0;
-        while start != path_length - 1 {
+        while start + 1 < path_length {
             let mut low = start;
             let mut high = path_length;
             let mut best_pref = None;
             let mut best_cut = 0;
             loop {
+                if observer.cancelled() {
+                    info!("Cancelled the preference search of path {}", path.id);
+                    return false;
+                }
                 let m = (low + high) / 2;
+                observer.probe_started(start, m);
                 let mut estimator = PreferenceEstimator::new(self);
-                let pref = estimator.calc_preference(&path, start, m);
+                let pref = estimator.calc_preference_observed(path, start, m, observer);
+                observer.probe_finished(start, m, pref.as_ref());
                 if pref.is_some() {
                     low = m + 1;
                     best_pref = pref;
@@ -159,13 +418,32 @@ impl Graph {
                     high = m;
                 }
                 if low == high {
-                    alphas.push(best_pref.unwrap());
-                    cuts.push(best_cut);
                     break;
                 }
             }
+            match best_pref {
+                Some(pref) => {
+                    observer.segment_found(start, best_cut, &pref);
+                    alphas.push(pref);
+                }
+                None => {
+                    metrics.learning_failures.inc();
+                    warn!(
+                        "No preference explains path {} from node {}",
+                        path.id, path.nodes[start]
+                    );
+                    path.algo_split = None;
+                    return true;
+                }
+            }
+            cuts.push(best_cut);
             start = best_cut;
         }
+        let num_of_alphas = alphas.len();
+        metrics.learning_segments.observe(num_of_alphas as f64);
+        metrics
+            .learning_seconds
+            .observe(started.elapsed().as_secs_f64());
         let dimension_costs = Vec::new();
         let costs_by_alpha = Vec::new();
         path.algo_split = Some(PathSplit {
@@ -174,9 +452,32 @@ impl Graph {
             dimension_costs,
             costs_by_alpha,
         });
-        println!("=== Found Preference ===");
+        info!("Found {} preferences for path {}", num_of_alphas, path.id);
+        true
     }
 
+    /// Regions of alphas that share the same optimal route between the nodes
+    pub fn find_preference_partition(
+        &self,
+        source: usize,
+        target: usize,
+    ) -> Option<Vec<PreferenceRegion>> {
+        let mut partition = PreferencePartition::new(self);
+        partition.calc_partition(source, target)
+    }
+
+    /// How far the alpha can change before the path is no longer optimal, None
+    /// if the path is not optimal for the alpha
+    pub fn find_sensitivity(
+        &self,
+        path: &Path,
+        alpha: Preference,
+    ) -> Option<PreferenceSensitivity> {
+        let mut analysis = SensitivityAnalysis::new(self);
+        analysis.calc_sensitivity(path, alpha)
+    }
+
+    /// Node with the smallest euclidean distance of its coordinates
     pub fn find_closest_node(&self, point: &Coordinate) -> &Node {
         self.nodes
             .iter()
@@ -192,18 +493,90 @@ impl Graph {
         &self.half_edges_in[self.offsets_in[node_id]..self.offsets_in[node_id + 1]]
     }
 
-    fn unpack_edge(&self, edge: usize) -> Vec<usize> {
-        if let Some((edge1, edge2)) = self.edges[edge].replaced_edges {
-            let mut first = self.unpack_edge(edge1);
-            first.extend(self.unpack_edge(edge2).iter());
-            return first;
+    fn get_edges_out(&self, node_id: usize) -> &[HalfEdge] {
+        &self.half_edges_orig_out
+            [self.offsets_orig_out[node_id]..self.offsets_orig_out[node_id + 1]]
+    }
+
+    fn get_edges_in(&self, node_id: usize) -> &[HalfEdge] {
+        &self.half_edges_orig_in[self.offsets_orig_in[node_id]..self.offsets_orig_in[node_id + 1]]
+    }
+
+    /// Appends the original edges that the edge consists of to the buffer
+    fn unpack_edge(&self, edge: usize, unpacked: &mut Vec<usize>) {
+        if let Some(table) = &self.unpack_table {
+            let (start, end) = (table.offsets[edge], table.offsets[edge + 1]);
+            if start != end {
+                unpacked.extend_from_slice(&table.edges[start..end]);
+                return;
+            }
+        }
+        let mut stack = vec![edge];
+        while let Some(edge) = stack.pop() {
+            match self.edges[edge].replaced_edges {
+                Some((first, second)) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                None => unpacked.push(edge),
+            }
+        }
+    }
+
+    /// Precomputes the unpacked original edges of every shortcut, so that routes
+    /// can be unpacked by copying. The memory needed grows with the depth of the
+    /// hierarchy.
+    pub fn build_unpack_table(&mut self) {
+        info!("Building unpack table");
+        self.unpack_table = None;
+        let mut offsets = vec![0; self.edges.len() + 1];
+        let mut edges = Vec::new();
+        for edge in 0..self.edges.len() {
+            if self.edges[edge].replaced_edges.is_some() {
+                self.unpack_edge(edge, &mut edges);
+            }
+            offsets[edge + 1] = edges.len();
+        }
+        self.unpack_table = Some(UnpackTable { offsets, edges });
+    }
+}
+
+/// FNV-1a hash of the ids and locations of the nodes and the ids and end points
+/// of the original edges. Shortcuts are left out, so that a recontracted graph
+/// keeps the fingerprint of its road network.
+fn calc_fingerprint(nodes: &[Node], edges: &[Edge]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |value: u64| {
+        for byte in value.to_le_bytes().iter() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
         }
-        vec![edge]
+    };
+    for node in nodes {
+        add(node.id as u64);
+        add(node.location.lat.to_bits());
+        add(node.location.lng.to_bits());
     }
+    for edge in edges.iter().filter(|edge| edge.replaced_edges.is_none()) {
+        add(edge.id as u64);
+        add(edge.source_id as u64);
+        add(edge.target_id as u64);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Parses the graph file and applies the settings of the config
+pub fn load_graph(name: &str, file_path: &str) -> Result<Graph, Box<dyn std::error::Error>> {
+    let mut graph = parse_graph_file(file_path)?;
+    graph.set_name(name);
+    graph.file_path = String::from(file_path);
+    graph.configure(get_config());
+    Ok(graph)
 }
 
+/// Reads a graph file without applying the config, see the README for the format
 pub fn parse_graph_file(file_path: &str) -> Result<Graph, Box<dyn std::error::Error>> {
-    println!("Parsing graph...");
+    info!("Parsing graph {}", file_path);
     let mut nodes: Vec<Node> = Vec::new();
     let mut edges: Vec<Edge> = Vec::new();
     let file = File::open(file_path)?;
@@ -211,57 +584,126 @@ pub fn parse_graph_file(file_path: &str) -> Result<Graph, Box<dyn std::error::Er
     let mut lines = reader.lines();
     for _i in 0..4 {
         // comments and blanks
-        lines.next();
-    }
-    let cost_dim: usize = lines.next().expect("No edge cost dim given")?.parse()?;
-    assert_eq!(EDGE_COST_DIMENSION, cost_dim);
-    let num_of_nodes = lines
-        .next()
-        .expect("Number of nodes not present in file")?
-        .parse()?;
-    let num_of_edges = lines
-        .next()
-        .expect("Number of edges not present in file")?
-        .parse()?;
+        lines.next().transpose()?;
+    }
+    let mut header = |name: &str| -> Result<usize, Box<dyn std::error::Error>> {
+        let line = lines
+            .next()
+            .ok_or_else(|| format!("Number of {} not present in file", name))??;
+        line.trim()
+            .parse()
+            .map_err(|_| format!("Invalid number of {}: {}", name, line).into())
+    };
+    let cost_dim = header("edge costs")?;
+    if cost_dim != EDGE_COST_DIMENSION {
+        return Err(format!(
+            "The graph has {} edge costs instead of {}",
+            cost_dim, EDGE_COST_DIMENSION
+        )
+        .into());
+    }
+    let num_of_nodes = header("nodes")?;
+    let num_of_edges = header("edges")?;
 
     let mut parsed_nodes: usize = 0;
     let mut parsed_edges: usize = 0;
-    while let Some(Ok(line)) = lines.next() {
+    let mut num_of_turns: Option<usize> = None;
+    let mut turns: Vec<(usize, usize, Option<Costs>)> = Vec::new();
+    for line in lines {
+        let line = line?;
         let tokens: Vec<&str> = line.split_whitespace().collect();
-        if tokens[0] == "#" || tokens[0] == "\n" {
+        if tokens.is_empty() || tokens[0] == "#" {
             continue;
         }
         if parsed_nodes < num_of_nodes {
-            nodes.push(Node::new(
-                tokens[0].parse()?,
-                tokens[2].parse()?,
-                tokens[3].parse()?,
-                tokens[4].parse()?,
-                tokens[5].parse()?,
-            ));
+            let node = match tokens[..] {
+                [id, _, lat, lng, height, level, ..] => Node::new(
+                    id.parse()?,
+                    lat.parse()?,
+                    lng.parse()?,
+                    height.parse()?,
+                    level.parse()?,
+                ),
+                _ => return Err(format!("Node \"{}\" needs 6 values", line).into()),
+            };
+            if node.id >= num_of_nodes {
+                return Err(format!("Node \"{}\" has an unknown id", line).into());
+            }
+            nodes.push(node);
             parsed_nodes += 1;
         } else if parsed_edges < num_of_edges {
-            let replaced_edges = if tokens[tokens.len() - 2] == "-1" {
-                None
-            } else {
-                Some((
-                    tokens[tokens.len() - 2].parse()?,
-                    tokens[tokens.len() - 1].parse()?,
-                ))
+            let (source_id, target_id, costs, replaced_edges) = match tokens[..] {
+                [source_id, target_id, ref costs @ .., "-1", "-1"] => {
+                    (source_id, target_id, costs, None)
+                }
+                [source_id, target_id, ref costs @ .., first, second] => (
+                    source_id,
+                    target_id,
+                    costs,
+                    Some((first.parse()?, second.parse()?)),
+                ),
+                _ => return Err(format!("Edge \"{}\" has too few values", line).into()),
             };
-            edges.push(Edge::new(
+            let edge = Edge::new(
                 parsed_edges,
-                tokens[0].parse()?,
-                tokens[1].parse()?,
-                edge::parse_costs(&tokens[2..tokens.len() - 2]),
+                source_id.parse()?,
+                target_id.parse()?,
+                edge::parse_costs(costs).map_err(|err| format!("Edge \"{}\": {}", line, err))?,
                 replaced_edges,
-            ));
+            );
+            let unknown_edge = edge
+                .replaced_edges
+                .is_some_and(|(first, second)| first >= num_of_edges || second >= num_of_edges);
+            if edge.source_id >= num_of_nodes || edge.target_id >= num_of_nodes || unknown_edge {
+                return Err(format!("Edge \"{}\" uses unknown nodes or edges", line).into());
+            }
+            edges.push(edge);
             parsed_edges += 1;
         } else {
-            panic!("Something doesn't add up with the amount of nodes and edges in graph file");
+            match num_of_turns {
+                None => num_of_turns = Some(tokens[0].parse()?),
+                Some(num) if turns.len() < num => {
+                    // restricted turns have a "-" instead of costs
+                    let costs = match &tokens[..] {
+                        [_, _, "-"] => None,
+                        [_, _, costs @ ..] if costs.len() == EDGE_COST_DIMENSION => {
+                            Some(edge::parse_costs(costs)?)
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Turn \"{}\" needs two edges and {} costs or a -",
+                                line, EDGE_COST_DIMENSION
+                            )
+                            .into())
+                        }
+                    };
+                    turns.push((tokens[0].parse()?, tokens[1].parse()?, costs));
+                }
+                Some(_) => {
+                    return Err(format!(
+                        "Line \"{}\" follows the nodes, edges and turns of the graph file",
+                        line
+                    )
+                    .into())
+                }
+            }
         }
     }
-    Ok(Graph::new(nodes, edges))
+    if parsed_nodes < num_of_nodes || parsed_edges < num_of_edges {
+        return Err(format!(
+            "The graph file ends after {} of {} nodes and {} of {} edges",
+            parsed_nodes, num_of_nodes, parsed_edges, num_of_edges
+        )
+        .into());
+    }
+    if let Some(num) = num_of_turns.filter(|num| turns.len() < *num) {
+        return Err(format!("The graph file ends after {} of {} turns", turns.len(), num).into());
+    }
+    let mut graph = Graph::new(nodes, edges);
+    for (from_edge, to_edge, costs) in turns {
+        graph.add_turn(from_edge, to_edge, costs)?;
+    }
+    Ok(graph)
 }
 
 #[cfg(test)]
@@ -280,4 +722,146 @@ mod tests {
         assert_eq!(exp_offsets_out, graph.offsets_out);
         assert_eq!(exp_offsets_in, graph.offsets_in);
     }
+
+    #[test]
+    fn fingerprint() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let contracted = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        assert_eq!(graph.fingerprint(), contracted.fingerprint());
+        assert_eq!(16, graph.fingerprint().len());
+
+        let generated = generator::generate_grid_graph(3, 4);
+        assert_ne!(graph.fingerprint(), generated.fingerprint());
+    }
+
+    #[test]
+    fn turn_parsing() {
+        let graph = parse_graph_file("./src/test_graphs/turnTestGraph").unwrap();
+        assert_eq!(26, graph.edges.len());
+        assert!(graph.has_turn_rules());
+        assert_eq!(None, graph.get_turn_costs(4, 7));
+        assert_eq!(Some([0.5, 0.0, 0.0, 0.0]), graph.get_turn_costs(11, 14));
+        assert_eq!(Some([0.0; EDGE_COST_DIMENSION]), graph.get_turn_costs(7, 9));
+    }
+
+    #[test]
+    fn invalid_turn_lines() {
+        let content = std::fs::read_to_string("./src/test_graphs/turnTestGraph").unwrap();
+        let path = std::env::temp_dir().join("preference-routing-turn-lines-test");
+        let path = path.to_str().unwrap();
+        for line in &[
+            "4 7",
+            "4",
+            "11 14 0.5 0",
+            "11 14 0.5 0 0 0 0",
+            "11 14 a 0 0 0",
+        ] {
+            std::fs::write(path, content.replace("4 7 -", line)).unwrap();
+            assert!(parse_graph_file(path).is_err(), "{}", line);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn malformed_files() {
+        let content = std::fs::read_to_string("./src/test_graphs/testGraph").unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        // truncated in the header, the nodes and the edges
+        let mut files = vec![
+            lines[..5].join("\n"),
+            lines[..12].join("\n"),
+            lines[..lines.len() - 1].join("\n"),
+        ];
+        for (from, to) in &[
+            ("\n4\n12\n18\n", "\n3\n12\n18\n"),
+            ("\n4\n12\n18\n", "\n4\n11\n18\n"),
+            ("\n4\n12\n18\n", "\n4\n12\n19\n"),
+            ("\n11 0 0 0 0 0\n", "\n11 0 0\n"),
+            ("\n1 2 2 2 1 1 -1 -1\n", "\n1 2 2 2 1 -1 -1\n"),
+            ("\n1 2 2 2 1 1 -1 -1\n", "\n1 2\n"),
+            ("\n1 2 2 2 1 1 -1 -1\n", "\n1 12 2 2 1 1 -1 -1\n"),
+        ] {
+            assert!(content.contains(from));
+            files.push(content.replacen(from, to, 1));
+        }
+        // a turn section without the announced turn
+        files.push(format!("{}1\n", content));
+
+        let path = std::env::temp_dir().join("preference-routing-malformed-test");
+        let path = path.to_str().unwrap();
+        for file in &files {
+            std::fs::write(path, file).unwrap();
+            assert!(parse_graph_file(path).is_err(), "{}", file);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn unpack_edges() {
+        let mut graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        for _ in 0..2 {
+            let mut unpacked = Vec::new();
+            graph.unpack_edge(20, &mut unpacked);
+            assert_eq!(vec![5, 9, 12], unpacked);
+            graph.unpack_edge(24, &mut unpacked);
+            assert_eq!(vec![5, 9, 12, 8, 11, 14], unpacked);
+            graph.unpack_edge(3, &mut unpacked);
+            assert_eq!(vec![5, 9, 12, 8, 11, 14, 3], unpacked);
+
+            graph.build_unpack_table();
+        }
+    }
+
+    #[test]
+    fn optimized_waypoint_order() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let path = graph
+            .find_shortest_path_optimized(
+                0,
+                vec![2, 7, 4, 10],
+                [1.0, 0.0, 0.0, 0.0],
+                &Avoid::default(),
+            )
+            .unwrap();
+        assert_eq!(Some(vec![0, 2, 1, 3]), path.order);
+        assert_eq!(vec![4, 7, 9, 12], path.edges);
+        assert_eq!(vec![1, 2, 1], path.user_split.cuts);
+    }
+
+    #[test]
+    fn avoiding() {
+        let graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        let path = graph.find_shortest_path(0, vec![2, 10], alpha).unwrap();
+        assert_eq!(vec![4, 7, 9, 12], path.edges);
+
+        let avoid_edge = Avoid {
+            edges: vec![7],
+            ..Avoid::default()
+        };
+        let avoid_node = Avoid {
+            nodes: vec![4],
+            ..Avoid::default()
+        };
+        for avoid in &[avoid_edge, avoid_node] {
+            let detour = graph
+                .find_shortest_path_avoiding(0, vec![2, 10], alpha, avoid)
+                .unwrap();
+            assert_eq!(vec![5, 9, 12], detour.edges);
+            assert_eq!(vec![2, 5, 7, 10], detour.nodes);
+        }
+    }
+
+    #[test]
+    fn no_waypoints() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        assert!(graph.find_shortest_path(0, Vec::new(), alpha).is_none());
+        assert!(graph
+            .find_shortest_path_avoiding(0, Vec::new(), alpha, &Avoid::default())
+            .is_none());
+        assert!(graph
+            .find_shortest_path_optimized(0, Vec::new(), alpha, &Avoid::default())
+            .is_none());
+    }
 }
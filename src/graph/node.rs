@@ -1,6 +1,6 @@
 use crate::helpers::Coordinate;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Node {
     pub id: usize,
     pub height: f64,
@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
+use crate::graph::avoid::Avoid;
 use crate::graph::Graph;
-use crate::helpers::{add_edge_costs, Coordinate, Costs, Preference};
+use crate::helpers::{add_edge_costs, costs_by_alpha, Coordinate, Costs, Preference};
 use crate::EDGE_COST_DIMENSION;
+use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Parts of a path, each cut is the number of edges of a part
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct PathSplit {
     pub cuts: Vec<usize>,
     pub alphas: Vec<Preference>,
@@ -19,23 +24,317 @@ impl PathSplit {
     }
 }
 
+/// Route through a graph. The user split has a part for each leg between
+/// waypoints, the algo split the parts found by Graph::find_preference.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Path {
     pub id: usize,
+    // name of the graph the nodes and edges belong to
+    #[serde(default = "default_graph")]
+    pub graph: String,
+    // fingerprint of the graph the nodes and edges belong to
+    #[serde(skip)]
+    pub fingerprint: String,
+    // the graph was replaced and the route could not be found in the new one
+    #[serde(default)]
+    pub stale: bool,
     pub nodes: Vec<usize>,
     pub edges: Vec<usize>,
     pub waypoints: Vec<Coordinate>,
+    // indices of the requested waypoints in the order the waypoints are
+    // visited, None if the order was not optimized
+    #[serde(default)]
+    pub order: Option<Vec<usize>>,
+    // parts of the graph the route was found without, the ids belong to the
+    // graph it was found on and are dropped when it is matched to another one
+    #[serde(default)]
+    pub avoid: Avoid,
     pub coordinates: Vec<Coordinate>,
     pub user_split: PathSplit,
     pub algo_split: Option<PathSplit>,
     pub total_dimension_costs: Costs,
 }
 
+// routes stored before graphs had names belong to the default graph
+fn default_graph() -> String {
+    String::from("default")
+}
+
+/// Routes in the user database. Node and edge ids change whenever a graph is
+/// rebuilt, so routes are stored by their coordinates and matched to the
+/// current graph when they are loaded.
+pub mod stored {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{default_graph, Avoid, Path, PathSplit};
+    use crate::helpers::{Coordinate, Costs};
+
+    #[derive(Deserialize, Serialize)]
+    struct StoredRoute {
+        id: usize,
+        #[serde(default = "default_graph")]
+        graph: String,
+        // fingerprint of the graph the route was matched to, missing in old databases
+        #[serde(default)]
+        fingerprint: Option<String>,
+        #[serde(default)]
+        stale: bool,
+        waypoints: Vec<Coordinate>,
+        #[serde(default)]
+        order: Option<Vec<usize>>,
+        #[serde(default)]
+        avoid: Avoid,
+        coordinates: Vec<Coordinate>,
+        user_split: PathSplit,
+        algo_split: Option<PathSplit>,
+        total_dimension_costs: Costs,
+    }
+
+    pub fn serialize<S: Serializer>(routes: &[Path], serializer: S) -> Result<S::Ok, S::Error> {
+        let routes: Vec<StoredRoute> = routes
+            .iter()
+            .map(|route| StoredRoute {
+                id: route.id,
+                graph: route.graph.clone(),
+                fingerprint: Some(route.fingerprint.clone()).filter(|print| !print.is_empty()),
+                stale: route.stale,
+                waypoints: route.waypoints.clone(),
+                order: route.order.clone(),
+                avoid: route.avoid.clone(),
+                coordinates: route.coordinates.clone(),
+                user_split: route.user_split.clone(),
+                algo_split: route.algo_split.clone(),
+                total_dimension_costs: route.total_dimension_costs,
+            })
+            .collect();
+        routes.serialize(serializer)
+    }
+
+    /// Old databases also contain node and edge ids, which are ignored. The
+    /// routes have to be rematched before they are used.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Path>, D::Error> {
+        let routes = Vec::<StoredRoute>::deserialize(deserializer)?;
+        Ok(routes
+            .into_iter()
+            .map(|route| Path {
+                id: route.id,
+                graph: route.graph,
+                fingerprint: route.fingerprint.unwrap_or_default(),
+                stale: route.stale,
+                nodes: Vec::new(),
+                edges: Vec::new(),
+                waypoints: route.waypoints,
+                order: route.order,
+                avoid: route.avoid,
+                coordinates: route.coordinates,
+                user_split: route.user_split,
+                algo_split: route.algo_split,
+                total_dimension_costs: route.total_dimension_costs,
+            })
+            .collect())
+    }
+}
+
+// nodes of a graph by the bits of their coordinates
+pub type LocationIndex = HashMap<(u64, u64), usize>;
+
+pub fn index_locations(graph: &Graph) -> LocationIndex {
+    graph
+        .nodes
+        .iter()
+        .map(|node| (location_key(&node.location), node.id))
+        .collect()
+}
+
+fn location_key(coordinate: &Coordinate) -> (u64, u64) {
+    (coordinate.lat.to_bits(), coordinate.lng.to_bits())
+}
+
 impl Path {
+    /// Costs of the edges from node start to node end, with the turns between them
     pub fn get_subpath_costs(&self, graph: &Graph, start: usize, end: usize) -> Costs {
         let edges = &self.edges[start..end];
-        edges.iter().fold([0.0; EDGE_COST_DIMENSION], |acc, edge| {
+        let costs = edges.iter().fold([0.0; EDGE_COST_DIMENSION], |acc, edge| {
             add_edge_costs(acc, graph.edges[*edge].edge_costs)
+        });
+        (start + 1..end).fold(costs, |acc, index| {
+            add_edge_costs(acc, self.get_turn_costs(graph, index))
+        })
+    }
+
+    /// Costs of turning into the edge at the index from the one before
+    fn get_turn_costs(&self, graph: &Graph, index: usize) -> Costs {
+        if !graph.has_turn_rules() {
+            return [0.0; EDGE_COST_DIMENSION];
+        }
+        // routes never take restricted turns
+        graph
+            .get_turn_costs(self.edges[index - 1], self.edges[index])
+            .unwrap_or([0.0; EDGE_COST_DIMENSION])
+    }
+
+    pub fn get_waypoint_nodes(&self) -> Vec<usize> {
+        let mut position = 0;
+        let mut waypoint_nodes = vec![self.nodes[0]];
+        for cut in &self.user_split.cuts {
+            position += cut;
+            waypoint_nodes.push(self.nodes[position]);
+        }
+        waypoint_nodes
+    }
+
+    /// Whether the nodes of the path lie at its coordinates and its edges
+    /// connect them in the graph
+    pub fn matches(&self, graph: &Graph) -> bool {
+        let nodes_match = self.nodes.len() == self.coordinates.len()
+            && self
+                .nodes
+                .iter()
+                .zip(&self.coordinates)
+                .all(|(node, coordinate)| match graph.nodes.get(*node) {
+                    Some(node) => node.location == *coordinate,
+                    None => false,
+                });
+        nodes_match
+            && self.edges.len() + 1 == self.nodes.len()
+            && self
+                .edges
+                .iter()
+                .zip(self.nodes.windows(2))
+                .all(|(edge, win)| match graph.edges.get(*edge) {
+                    Some(edge) => {
+                        edge.replaced_edges.is_none()
+                            && edge.source_id == win[0]
+                            && edge.target_id == win[1]
+                    }
+                    None => false,
+                })
+    }
+
+    /// Finds the nodes and edges of the path in a replaced graph by the coordinates
+    /// of its nodes and recomputes its costs. Returns false and leaves the path
+    /// unchanged if a coordinate or a connection is missing.
+    pub fn rematch(&mut self, graph: &Graph, locations: &LocationIndex) -> bool {
+        let nodes: Option<Vec<usize>> = self
+            .coordinates
+            .iter()
+            .map(|coordinate| locations.get(&location_key(coordinate)).cloned())
+            .collect();
+        let nodes = match nodes {
+            Some(nodes) => nodes,
+            None => return false,
+        };
+        let alpha = self.user_split.alphas[0];
+        let edges: Option<Vec<usize>> = nodes
+            .windows(2)
+            .map(|win| {
+                graph
+                    .get_edges_out(win[0])
+                    .iter()
+                    .filter(|half_edge| half_edge.target_id == win[1])
+                    .min_by_key(|half_edge| {
+                        OrderedFloat(costs_by_alpha(half_edge.edge_costs, alpha))
+                    })
+                    .map(|half_edge| half_edge.edge_id)
+            })
+            .collect();
+        let edges = match edges {
+            Some(edges) => edges,
+            None => return false,
+        };
+
+        self.nodes = nodes;
+        self.edges = edges;
+        self.total_dimension_costs = self.get_subpath_costs(graph, 0, self.edges.len());
+        let mut start = 0;
+        let mut dimension_costs = Vec::new();
+        let mut costs = Vec::new();
+        for (index, cut) in self.user_split.cuts.iter().enumerate() {
+            let mut leg_costs = self.get_subpath_costs(graph, start, start + cut);
+            // the turn at a waypoint belongs to the leg that leaves it
+            if start > 0 && *cut > 0 {
+                leg_costs = add_edge_costs(leg_costs, self.get_turn_costs(graph, start));
+            }
+            let leg_alpha = self.user_split.alphas.get(index).unwrap_or(&alpha);
+            dimension_costs.push(leg_costs);
+            costs.push(costs_by_alpha(leg_costs, *leg_alpha));
+            start += cut;
+        }
+        self.user_split.dimension_costs = dimension_costs;
+        self.user_split.costs_by_alpha = costs;
+        if self.fingerprint != graph.fingerprint() {
+            self.avoid = self.avoid.polygons_only();
+        }
+        self.fingerprint = String::from(graph.fingerprint());
+        self.stale = false;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::generator::generate_grid_graph;
+
+    #[test]
+    fn rematch() {
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        let graph = generate_grid_graph(4, 4);
+        let path = graph.find_shortest_path(0, vec![0, 15], alpha).unwrap();
+        assert!(path.matches(&graph));
+
+        // the same coordinates with other node and edge ids
+        let wider = generate_grid_graph(5, 4);
+        assert!(!path.matches(&wider));
+        let mut rematched = path.clone();
+        assert!(rematched.rematch(&wider, &index_locations(&wider)));
+        assert!(rematched.matches(&wider));
+        assert_eq!(path.coordinates, rematched.coordinates);
+        assert_eq!(path.total_dimension_costs, rematched.total_dimension_costs);
+        assert_eq!(
+            path.user_split.costs_by_alpha,
+            rematched.user_split.costs_by_alpha
+        );
+
+        let wider_print = String::from(wider.fingerprint());
+        assert_eq!(wider_print, rematched.fingerprint);
+
+        let smaller = generate_grid_graph(2, 2);
+        let mut rematched = path.clone();
+        assert!(!rematched.rematch(&smaller, &index_locations(&smaller)));
+        assert_eq!(path.edges, rematched.edges);
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct Routes {
+        #[serde(with = "stored")]
+        routes: Vec<Path>,
+    }
+
+    #[test]
+    fn stored_routes() {
+        let graph = generate_grid_graph(4, 4);
+        let path = graph
+            .find_shortest_path(0, vec![0, 15], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        let json = serde_json::to_value(Routes {
+            routes: vec![path.clone()],
         })
+        .unwrap();
+        assert!(json["routes"][0].get("edges").is_none());
+        assert_eq!(graph.fingerprint(), json["routes"][0]["fingerprint"]);
+
+        let mut stored: Routes = serde_json::from_value(json).unwrap();
+        let route = &mut stored.routes[0];
+        assert!(!route.matches(&graph));
+        assert!(route.rematch(&graph, &index_locations(&graph)));
+        assert_eq!(path.nodes, route.nodes);
+        assert_eq!(path.edges, route.edges);
+
+        // routes stored with their ids before are read as well
+        let json = serde_json::json!({ "routes": [path] });
+        let stored: Routes = serde_json::from_value(json).unwrap();
+        assert!(stored.routes[0].edges.is_empty());
+        assert_eq!("", stored.routes[0].fingerprint);
     }
 }
@@ -0,0 +1,207 @@
+// Largest number of intermediate waypoints that is ordered exactly
+const EXACT_LIMIT: usize = 10;
+
+/// Orders the waypoints of a cost matrix so that the first and the last one stay
+/// fixed and the sum of the costs between successive waypoints is minimal.
+///
+/// Small inputs are solved exactly with the Held-Karp algorithm, larger ones
+/// with a nearest neighbor tour that is improved by local search.
+/// Returns the indices of the waypoints in the chosen order, or None if no
+/// order connects all of them.
+pub fn find_order(costs: &[Vec<f64>]) -> Option<Vec<usize>> {
+    let num_of_waypoints = costs.len();
+    let order = if num_of_waypoints <= 3 {
+        (0..num_of_waypoints).collect()
+    } else if num_of_waypoints - 2 <= EXACT_LIMIT {
+        held_karp(costs)
+    } else {
+        let mut order = nearest_neighbor(costs);
+        improve(costs, &mut order);
+        order
+    };
+    // an incomplete order means that some waypoints are unreachable
+    if order.len() == num_of_waypoints && is_connected(costs, &order) {
+        Some(order)
+    } else {
+        None
+    }
+}
+
+pub fn get_order_cost(costs: &[Vec<f64>], order: &[usize]) -> f64 {
+    order
+        .windows(2)
+        .fold(0.0, |acc, win| acc + costs[win[0]][win[1]])
+}
+
+fn is_connected(costs: &[Vec<f64>], order: &[usize]) -> bool {
+    order
+        .windows(2)
+        .all(|win| costs[win[0]][win[1]] != std::f64::MAX)
+}
+
+fn held_karp(costs: &[Vec<f64>]) -> Vec<usize> {
+    let last = costs.len() - 1;
+    // intermediate waypoint i is represented by bit i - 1
+    let num_of_intermediates = last - 1;
+    let num_of_sets = 1 << num_of_intermediates;
+
+    // best cost from the start through all waypoints in the set, ending in the waypoint
+    let mut best = vec![vec![std::f64::INFINITY; num_of_intermediates]; num_of_sets];
+    let mut previous = vec![vec![None; num_of_intermediates]; num_of_sets];
+    for waypoint in 0..num_of_intermediates {
+        best[1 << waypoint][waypoint] = costs[0][waypoint + 1];
+    }
+    for set in 1..num_of_sets {
+        for end in 0..num_of_intermediates {
+            if set & (1 << end) == 0 || best[set][end] == std::f64::INFINITY {
+                continue;
+            }
+            for next in 0..num_of_intermediates {
+                if set & (1 << next) != 0 {
+                    continue;
+                }
+                let next_set = set | (1 << next);
+                let next_cost = best[set][end] + costs[end + 1][next + 1];
+                if next_cost < best[next_set][next] {
+                    best[next_set][next] = next_cost;
+                    previous[next_set][next] = Some(end);
+                }
+            }
+        }
+    }
+
+    let full_set = num_of_sets - 1;
+    let mut end = (0..num_of_intermediates)
+        .min_by(|a, b| {
+            let cost_a = best[full_set][*a] + costs[*a + 1][last];
+            let cost_b = best[full_set][*b] + costs[*b + 1][last];
+            cost_a.partial_cmp(&cost_b).unwrap()
+        })
+        .unwrap();
+
+    let mut order = vec![last];
+    let mut set = full_set;
+    loop {
+        order.push(end + 1);
+        let prev = previous[set][end];
+        set &= !(1 << end);
+        match prev {
+            Some(prev) => end = prev,
+            None => break,
+        }
+    }
+    order.push(0);
+    order.reverse();
+    order
+}
+
+fn nearest_neighbor(costs: &[Vec<f64>]) -> Vec<usize> {
+    let last = costs.len() - 1;
+    let mut remaining: Vec<usize> = (1..last).collect();
+    let mut order = vec![0];
+    while !remaining.is_empty() {
+        let current = order[order.len() - 1];
+        let (index, _) = remaining
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                costs[current][**a]
+                    .partial_cmp(&costs[current][**b])
+                    .unwrap()
+            })
+            .unwrap();
+        order.push(remaining.remove(index));
+    }
+    order.push(last);
+    order
+}
+
+/// Applies segment reversals and single waypoint moves as long as they lower
+/// the cost. Costs may be asymmetric, so every candidate is evaluated in full.
+fn improve(costs: &[Vec<f64>], order: &mut Vec<usize>) {
+    let last = order.len() - 1;
+    let mut best_cost = get_order_cost(costs, order);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 1..last {
+            for j in (i + 1)..last {
+                let mut candidate = order.clone();
+                candidate[i..=j].reverse();
+                let cost = get_order_cost(costs, &candidate);
+                if cost < best_cost {
+                    *order = candidate;
+                    best_cost = cost;
+                    improved = true;
+                }
+            }
+        }
+        for from in 1..last {
+            for to in 1..last {
+                if from == to {
+                    continue;
+                }
+                let mut candidate = order.clone();
+                let waypoint = candidate.remove(from);
+                candidate.insert(to, waypoint);
+                let cost = get_order_cost(costs, &candidate);
+                if cost < best_cost {
+                    *order = candidate;
+                    best_cost = cost;
+                    improved = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_costs() -> Vec<Vec<f64>> {
+        // waypoints on a line at 0, 3, 1, 2, 4; the detour back is more expensive
+        let positions = [0.0, 3.0, 1.0, 2.0, 4.0];
+        positions
+            .iter()
+            .map(|from: &f64| {
+                positions
+                    .iter()
+                    .map(|to| {
+                        if to >= from {
+                            to - from
+                        } else {
+                            2.0 * (from - to)
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exact_order() {
+        let costs = get_costs();
+        let order = find_order(&costs).unwrap();
+        assert_eq!(vec![0, 2, 3, 1, 4], order);
+        assert_eq!(4.0, get_order_cost(&costs, &order));
+    }
+
+    #[test]
+    fn heuristic_order() {
+        let costs = get_costs();
+        let mut order = vec![0, 1, 3, 2, 4];
+        improve(&costs, &mut order);
+        assert_eq!(vec![0, 2, 3, 1, 4], order);
+        assert_eq!(vec![0, 2, 3, 1, 4], nearest_neighbor(&costs));
+    }
+
+    #[test]
+    fn disconnected() {
+        let mut costs = get_costs();
+        costs[1][4] = std::f64::MAX;
+        costs[2][4] = std::f64::MAX;
+        costs[3][4] = std::f64::MAX;
+        assert!(find_order(&costs).is_none());
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp::Reverse;
+use std::collections::binary_heap::BinaryHeap;
+
+use log::debug;
+use ordered_float::OrderedFloat;
+
+use crate::graph::dijkstra::{DijkstraResult, QueryStats};
+use crate::graph::Graph;
+use crate::helpers::{add_edge_costs, costs_by_alpha, Costs, Preference};
+use crate::EDGE_COST_DIMENSION;
+
+impl Graph {
+    /// Adds a turn between two successive original edges. Turns without costs
+    /// are restricted.
+    pub fn add_turn(
+        &mut self,
+        from_edge: usize,
+        to_edge: usize,
+        costs: Option<Costs>,
+    ) -> Result<(), String> {
+        let (from, to) = match (self.edges.get(from_edge), self.edges.get(to_edge)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => {
+                return Err(format!(
+                    "Turn {} -> {} uses unknown edges",
+                    from_edge, to_edge
+                ))
+            }
+        };
+        if from.replaced_edges.is_some() || to.replaced_edges.is_some() {
+            return Err(format!(
+                "Turn {} -> {} uses shortcuts, turns are only defined between original edges",
+                from_edge, to_edge
+            ));
+        }
+        if from.target_id != to.source_id {
+            return Err(format!(
+                "Edges {} and {} of turn {} -> {} are not successive",
+                from_edge, to_edge, from_edge, to_edge
+            ));
+        }
+        self.turns.insert((from_edge, to_edge), costs);
+        Ok(())
+    }
+
+    pub fn set_forbid_u_turns(&mut self, forbid_u_turns: bool) {
+        self.forbid_u_turns = forbid_u_turns;
+    }
+
+    pub fn forbids_u_turns(&self) -> bool {
+        self.forbid_u_turns
+    }
+
+    /// Turns between original edges as (from edge, to edge, costs), ordered by
+    /// their edges. Restricted turns have no costs.
+    pub fn turns(&self) -> Vec<(usize, usize, Option<Costs>)> {
+        let mut turns: Vec<(usize, usize, Option<Costs>)> = self
+            .turns
+            .iter()
+            .map(|(&(from_edge, to_edge), costs)| (from_edge, to_edge, *costs))
+            .collect();
+        turns.sort_unstable_by_key(|(from_edge, to_edge, _)| (*from_edge, *to_edge));
+        turns
+    }
+
+    /// Whether routes have to be searched on the edges instead of the nodes
+    pub fn has_turn_rules(&self) -> bool {
+        self.forbid_u_turns || !self.turns.is_empty()
+    }
+
+    /// Costs of turning from one original edge into the next one, None if the
+    /// turn is restricted
+    pub fn get_turn_costs(&self, from_edge: usize, to_edge: usize) -> Option<Costs> {
+        if self.forbid_u_turns && self.edges[from_edge].source_id == self.edges[to_edge].target_id {
+            return None;
+        }
+        match self.turns.get(&(from_edge, to_edge)) {
+            Some(costs) => *costs,
+            None => Some([0.0; EDGE_COST_DIMENSION]),
+        }
+    }
+}
+
+/// Dijkstra on the original edges instead of the nodes, so that the cost of
+/// reaching an edge can depend on the edge it was entered from. The contraction
+/// hierarchy does not know about turns and is not used.
+///
+/// The turn at the source is only known if the search continues a route that
+/// arrived on a given edge, otherwise it is free.
+pub struct TurnSearch<'a> {
+    graph: &'a Graph,
+    // edges that must not be used, indexed by edge id
+    blocked: Option<&'a [bool]>,
+    cost: Vec<(Costs, f64)>,
+    previous: Vec<Option<usize>>,
+    touched_edges: Vec<usize>,
+}
+
+impl<'a> TurnSearch<'a> {
+    pub fn new(graph: &'a Graph, blocked: Option<&'a [bool]>) -> Self {
+        let num_of_edges = graph.edges.len();
+        TurnSearch {
+            graph,
+            blocked,
+            cost: vec![([0.0; EDGE_COST_DIMENSION], std::f64::MAX); num_of_edges],
+            previous: vec![None; num_of_edges],
+            touched_edges: Vec::new(),
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        source: usize,
+        target: usize,
+        alpha: Preference,
+    ) -> Option<DijkstraResult> {
+        self.run_after(None, source, target, alpha)
+    }
+
+    /// Like run, but the route arrives at the source on the incoming edge, so
+    /// the first turn is restricted or costs like every other one
+    pub fn run_after(
+        &mut self,
+        incoming: Option<usize>,
+        source: usize,
+        target: usize,
+        alpha: Preference,
+    ) -> Option<DijkstraResult> {
+        for edge in self.touched_edges.drain(..) {
+            self.cost[edge] = ([0.0; EDGE_COST_DIMENSION], std::f64::MAX);
+            self.previous[edge] = None;
+        }
+        let mut stats = QueryStats::default();
+        if source == target {
+            return Some(DijkstraResult {
+                edges: Vec::new(),
+                costs: [0.0; EDGE_COST_DIMENSION],
+                total_cost: 0.0,
+                stats,
+            });
+        }
+
+        let mut candidates = BinaryHeap::new();
+        for half_edge in self.graph.get_edges_out(source) {
+            if self.is_blocked(half_edge.edge_id) {
+                continue;
+            }
+            let turn_costs = match incoming {
+                Some(incoming) => match self.graph.get_turn_costs(incoming, half_edge.edge_id) {
+                    Some(turn_costs) => turn_costs,
+                    None => continue,
+                },
+                None => [0.0; EDGE_COST_DIMENSION],
+            };
+            let costs = add_edge_costs(turn_costs, half_edge.edge_costs);
+            let total_cost = costs_by_alpha(costs, alpha);
+            self.cost[half_edge.edge_id] = (costs, total_cost);
+            self.touched_edges.push(half_edge.edge_id);
+            candidates.push((Reverse(OrderedFloat(total_cost)), half_edge.edge_id));
+        }
+
+        while let Some((Reverse(OrderedFloat(total_cost)), edge_id)) = candidates.pop() {
+            if total_cost > self.cost[edge_id].1 {
+                continue;
+            }
+            stats.nodes_popped += 1;
+            let costs = self.cost[edge_id].0;
+            let node_id = self.graph.edges[edge_id].target_id;
+            if node_id == target {
+                let edges = self.make_edge_path(edge_id);
+                debug!(
+                    "Found path with dim_costs {:?} and cost {:?} ({:?})",
+                    costs, total_cost, stats
+                );
+                return Some(DijkstraResult {
+                    edges,
+                    costs,
+                    total_cost,
+                    stats,
+                });
+            }
+
+            for half_edge in self.graph.get_edges_out(node_id) {
+                let next_edge = half_edge.edge_id;
+                if self.is_blocked(next_edge) {
+                    continue;
+                }
+                let turn_costs = match self.graph.get_turn_costs(edge_id, next_edge) {
+                    Some(turn_costs) => turn_costs,
+                    None => continue,
+                };
+                stats.edges_relaxed += 1;
+                let step_costs = add_edge_costs(turn_costs, half_edge.edge_costs);
+                let next_total_cost = total_cost + costs_by_alpha(step_costs, alpha);
+                if next_total_cost < self.cost[next_edge].1 {
+                    self.cost[next_edge] = (add_edge_costs(costs, step_costs), next_total_cost);
+                    self.previous[next_edge] = Some(edge_id);
+                    self.touched_edges.push(next_edge);
+                    candidates.push((Reverse(OrderedFloat(next_total_cost)), next_edge));
+                }
+            }
+        }
+        None
+    }
+
+    fn is_blocked(&self, edge_id: usize) -> bool {
+        self.blocked.is_some_and(|blocked| blocked[edge_id])
+    }
+
+    fn make_edge_path(&self, last_edge: usize) -> Vec<usize> {
+        let mut edges = vec![last_edge];
+        let mut current = last_edge;
+        while let Some(previous) = self.previous[current] {
+            edges.push(previous);
+            current = previous;
+        }
+        edges.reverse();
+        edges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::parse_graph_file;
+
+    fn get_graph() -> Graph {
+        parse_graph_file("./src/test_graphs/concTestGraph").unwrap()
+    }
+
+    #[test]
+    fn invalid_turns() {
+        let mut graph = get_graph();
+        // edge 4 is 2 -> 4, edge 7 is 4 -> 5, edge 9 is 5 -> 7
+        assert!(graph.add_turn(4, 7, None).is_ok());
+        assert!(graph.add_turn(4, 9, None).is_err());
+        assert!(graph.add_turn(4, 19, None).is_err());
+        assert!(graph.add_turn(4, 100, None).is_err());
+    }
+
+    #[test]
+    fn restricted_turn() {
+        let mut graph = get_graph();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        graph.add_turn(4, 7, None).unwrap();
+        let path = graph.find_shortest_path(0, vec![2, 10], alpha).unwrap();
+        assert_eq!(vec![5, 9, 12], path.edges);
+    }
+
+    #[test]
+    fn restricted_turn_at_waypoint() {
+        let mut graph = get_graph();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        let path = graph.find_shortest_path(0, vec![2, 4, 5], alpha).unwrap();
+        assert_eq!(vec![4, 7], path.edges);
+
+        // edge 4 is 2 -> 4, edge 7 is 4 -> 5, the route has to come back to 4 on edge 10
+        graph.add_turn(4, 7, None).unwrap();
+        let path = graph.find_shortest_path(0, vec![2, 4, 5], alpha).unwrap();
+        assert_eq!(vec![4, 8, 10, 7], path.edges);
+        assert_eq!(vec![2, 4, 6, 4, 5], path.nodes);
+    }
+
+    #[test]
+    fn u_turn_at_waypoint() {
+        let mut graph = get_graph();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        // edge 14 is 8 -> 9, edge 15 is 9 -> 8
+        let path = graph.find_shortest_path(0, vec![8, 9, 8], alpha).unwrap();
+        assert_eq!(vec![14, 15], path.edges);
+        // every way back from 9 turns around somewhere
+        graph.set_forbid_u_turns(true);
+        assert!(graph.find_shortest_path(0, vec![8, 9, 8], alpha).is_none());
+    }
+
+    #[test]
+    fn turn_costs() {
+        let mut graph = get_graph();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        graph.add_turn(4, 7, Some([0.5, 0.0, 0.0, 0.0])).unwrap();
+        let path = graph.find_shortest_path(0, vec![2, 10], alpha).unwrap();
+        assert_eq!(vec![4, 7, 9, 12], path.edges);
+        assert_eq!([4.5, 4.0, 4.0, 4.0], path.total_dimension_costs);
+        assert_eq!(
+            path.total_dimension_costs,
+            path.get_subpath_costs(&graph, 0, path.edges.len())
+        );
+        // the turn lies between the first two edges
+        assert_eq!([2.5, 2.0, 2.0, 2.0], path.get_subpath_costs(&graph, 0, 2));
+        assert_eq!([2.0, 2.0, 2.0, 2.0], path.get_subpath_costs(&graph, 1, 3));
+
+        graph.add_turn(4, 7, Some([1.5, 0.0, 0.0, 0.0])).unwrap();
+        let path = graph.find_shortest_path(0, vec![2, 10], alpha).unwrap();
+        assert_eq!(vec![5, 9, 12], path.edges);
+    }
+
+    #[test]
+    fn u_turns() {
+        let mut graph = get_graph();
+        // edge 14 is 8 -> 9, edge 15 is 9 -> 8
+        assert_eq!(
+            Some([0.0; EDGE_COST_DIMENSION]),
+            graph.get_turn_costs(14, 15)
+        );
+        graph.set_forbid_u_turns(true);
+        assert!(graph.has_turn_rules());
+        assert_eq!(None, graph.get_turn_costs(14, 15));
+        assert!(graph.get_turn_costs(14, 16).is_some());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::graph::Graph;
+use crate::helpers::add_edge_costs;
+
+impl Graph {
+    /// Checks the graph for inconsistencies that the parser does not catch and
+    /// returns a description of each one. An empty list means the graph is fine.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.id != index {
+                problems.push(format!(
+                    "Node ids are not consecutive, expected {} but found {}",
+                    index, node.id
+                ));
+                // every following id is off as well
+                break;
+            }
+        }
+        for node in &self.nodes {
+            let location = &node.location;
+            if !(-90.0..=90.0).contains(&location.lat) || !(-180.0..=180.0).contains(&location.lng)
+            {
+                problems.push(format!(
+                    "Node {} lies outside of the valid coordinates ({}, {})",
+                    node.id, location.lat, location.lng
+                ));
+            }
+        }
+
+        for edge in &self.edges {
+            if edge
+                .edge_costs
+                .iter()
+                .any(|cost| !cost.is_finite() || *cost < 0.0)
+            {
+                problems.push(format!(
+                    "Edge {} has invalid costs {:?}",
+                    edge.id, edge.edge_costs
+                ));
+            }
+            let (first, second) = match edge.replaced_edges {
+                Some(replaced) => replaced,
+                None => continue,
+            };
+            let (first, second) = match (self.edges.get(first), self.edges.get(second)) {
+                (Some(first), Some(second)) => (first, second),
+                _ => {
+                    problems.push(format!(
+                        "Shortcut {} replaces unknown edges {} and {}",
+                        edge.id, first, second
+                    ));
+                    continue;
+                }
+            };
+            if first.source_id != edge.source_id
+                || first.target_id != second.source_id
+                || second.target_id != edge.target_id
+            {
+                problems.push(format!(
+                    "Shortcut {} does not lead along its edges {} and {}",
+                    edge.id, first.id, second.id
+                ));
+                continue;
+            }
+            let costs = add_edge_costs(first.edge_costs, second.edge_costs);
+            if costs
+                .iter()
+                .zip(edge.edge_costs.iter())
+                .any(|(expected, cost)| (expected - cost).abs() > 1e-9 * expected.abs().max(1.0))
+            {
+                problems.push(format!(
+                    "Shortcut {} costs {:?}, but its edges {} and {} add up to {:?}",
+                    edge.id, edge.edge_costs, first.id, second.id, costs
+                ));
+            }
+            let level = self.nodes[first.target_id].ch_level;
+            if level >= self.nodes[edge.source_id].ch_level
+                || level >= self.nodes[edge.target_id].ch_level
+            {
+                problems.push(format!(
+                    "Shortcut {} skips node {}, which is not contracted before its end points",
+                    edge.id, first.target_id
+                ));
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::parse_graph_file;
+
+    #[test]
+    fn valid_graphs() {
+        for file in &["testGraph", "concTestGraph", "turnTestGraph"] {
+            let graph = parse_graph_file(&format!("./src/test_graphs/{}", file)).unwrap();
+            assert_eq!(Vec::<String>::new(), graph.validate());
+        }
+        let grid = crate::graph::generator::generate_grid_graph(6, 5);
+        assert_eq!(Vec::<String>::new(), grid.validate());
+    }
+
+    #[test]
+    fn invalid_shortcut() {
+        let mut graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+        // shortcut 24 is 4 -> 9 along 8 and 22, shortcut 25 is 9 -> 4 along 23 and 10
+        graph.edges[24].edge_costs[0] += 1.0;
+        graph.edges[25].replaced_edges = Some((10, 23));
+        let problems = graph.validate();
+        assert_eq!(2, problems.len());
+        assert!(problems[0].starts_with("Shortcut 24 costs"));
+        assert!(problems[1].starts_with("Shortcut 25 does not lead"));
+    }
+}
@@ -22,7 +22,7 @@ pub fn add_edge_costs(a: Costs, b: Costs) -> Costs {
     result
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub struct Coordinate {
     pub lat: f64,
     pub lng: f64,
@@ -36,10 +36,56 @@ impl Coordinate {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min: Coordinate,
+    pub max: Coordinate,
+}
+
+impl BoundingBox {
+    pub fn around<'a, I>(coordinates: I) -> BoundingBox
+    where
+        I: IntoIterator<Item = &'a Coordinate>,
+    {
+        let mut min = Coordinate {
+            lat: std::f64::MAX,
+            lng: std::f64::MAX,
+        };
+        let mut max = Coordinate {
+            lat: std::f64::MIN,
+            lng: std::f64::MIN,
+        };
+        for coordinate in coordinates {
+            min.lat = min.lat.min(coordinate.lat);
+            min.lng = min.lng.min(coordinate.lng);
+            max.lat = max.lat.max(coordinate.lat);
+            max.lng = max.lng.max(coordinate.lng);
+        }
+        BoundingBox { min, max }
+    }
+
+    pub fn contains(&self, point: &Coordinate) -> bool {
+        (self.min.lat..=self.max.lat).contains(&point.lat)
+            && (self.min.lng..=self.max.lng).contains(&point.lng)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_bounding_box() {
+        let coordinates = [
+            Coordinate { lat: 1.0, lng: 3.0 },
+            Coordinate { lat: 2.0, lng: 0.0 },
+        ];
+        let bounding_box = BoundingBox::around(&coordinates);
+        assert!(bounding_box.contains(&Coordinate { lat: 1.5, lng: 2.0 }));
+        assert!(bounding_box.contains(&Coordinate { lat: 2.0, lng: 3.0 }));
+        assert!(!bounding_box.contains(&Coordinate { lat: 0.5, lng: 2.0 }));
+    }
+
     #[test]
     fn test_add_edge_costs() {
         let a = [1.5, 2.0, 0.7, 1.3];
@@ -0,0 +1,36 @@
+//! Routing with personal preferences on contraction hierarchies.
+//!
+//! Every edge has several kinds of costs, a preference (alpha) weights them
+//! into a single cost. Routes are searched for a given alpha, and the alphas
+//! that explain a driven route are learned with linear programs.
+//!
+//! ```
+//! use preference_routing::parse_graph_file;
+//!
+//! let graph = parse_graph_file("./src/test_graphs/concTestGraph").unwrap();
+//! let path = graph
+//!     .find_shortest_path(0, vec![2, 10], [1.0, 0.0, 0.0, 0.0])
+//!     .unwrap();
+//! assert_eq!(Some(&10), path.nodes.last());
+//! ```
+//!
+//! The server and the command-line tools are built on top of this crate.
+
+pub mod config;
+pub mod graph;
+pub mod helpers;
+pub mod logging;
+pub mod lp;
+pub mod metrics;
+pub mod server;
+mod user;
+
+pub use graph::avoid::Avoid;
+pub use graph::customize::EdgeUpdate;
+pub use graph::path::{Path, PathSplit};
+pub use graph::{load_graph, parse_graph_file, Edge, Graph, Node, QueryMode};
+pub use helpers::{BoundingBox, Coordinate, Costs, Preference};
+pub use lp::PreferenceEstimator;
+
+/// Number of costs of every edge, the config names them in edge_cost_tags
+pub const EDGE_COST_DIMENSION: usize = 4;
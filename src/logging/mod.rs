@@ -0,0 +1,244 @@
+use std::cell::Cell;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::{Future, Poll};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use serde_json::json;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // id of the request the current thread works on
+    static REQUEST_ID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // one human-readable line per message
+    #[default]
+    Text,
+    // one JSON object per line
+    Json,
+}
+
+struct Logger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_record(self.format, timestamp(), record, request_id());
+        // logging must not fail the caller, e.g. when stderr is closed
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger, which writes to stderr. Only the first call has an effect.
+pub fn init(level: LevelFilter, format: LogFormat) {
+    let logger = LOGGER.get_or_init(|| Logger { level, format });
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.level);
+    }
+}
+
+pub fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn request_id() -> Option<u64> {
+    REQUEST_ID.with(|id| id.get())
+}
+
+/// Marks all messages of the current thread with the request id until the
+/// guard is dropped
+pub fn enter_request(id: u64) -> RequestGuard {
+    let previous = REQUEST_ID.with(|current| current.replace(Some(id)));
+    RequestGuard { previous }
+}
+
+pub struct RequestGuard {
+    previous: Option<u64>,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|current| current.set(self.previous));
+    }
+}
+
+/// Runs every poll of the future with the request id, so that it carries over
+/// to the handlers, which may run in a later poll than the one that started the
+/// request
+pub struct WithRequestId<F> {
+    id: u64,
+    inner: F,
+}
+
+impl<F> WithRequestId<F> {
+    pub fn new(id: u64, inner: F) -> Self {
+        WithRequestId { id, inner }
+    }
+}
+
+impl<F: Future> Future for WithRequestId<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let _request = enter_request(self.id);
+        self.inner.poll()
+    }
+}
+
+pub fn format_request_id(id: u64) -> String {
+    format!("{:08x}", id)
+}
+
+fn format_record(
+    format: LogFormat,
+    timestamp: String,
+    record: &Record,
+    request_id: Option<u64>,
+) -> String {
+    let request_id = request_id.map(format_request_id);
+    match format {
+        LogFormat::Text => {
+            let request = request_id
+                .map(|id| format!(" [{}]", id))
+                .unwrap_or_default();
+            format!(
+                "{} {:5} {}{}: {}",
+                timestamp,
+                record.level(),
+                record.target(),
+                request,
+                record.args()
+            )
+        }
+        LogFormat::Json => json!({
+            "timestamp": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "request_id": request_id,
+            "message": record.args().to_string(),
+        })
+        .to_string(),
+    }
+}
+
+/// Current time in RFC 3339 with milliseconds, in UTC
+fn timestamp() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format_timestamp(elapsed.as_secs(), elapsed.subsec_millis())
+}
+
+fn format_timestamp(secs: u64, millis: u32) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // civil date from the days since 1970-01-01, see Howard Hinnant's date algorithms
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn record_line(format: LogFormat, request_id: Option<u64>) -> String {
+        format_record(
+            format,
+            String::from("2019-10-01T12:00:00.000Z"),
+            &Record::builder()
+                .args(format_args!("Found \"path\""))
+                .level(Level::Info)
+                .target("preference_routing::graph")
+                .build(),
+            request_id,
+        )
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            "2019-10-01T12:00:00.000Z INFO  preference_routing::graph [0000002a]: Found \"path\"",
+            record_line(LogFormat::Text, Some(42))
+        );
+        let line: serde_json::Value =
+            serde_json::from_str(&record_line(LogFormat::Json, None)).unwrap();
+        assert_eq!("INFO", line["level"]);
+        assert_eq!("Found \"path\"", line["message"]);
+        assert!(line["request_id"].is_null());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!("1970-01-01T00:00:00.000Z", format_timestamp(0, 0));
+        assert_eq!(
+            "2000-02-29T23:59:59.999Z",
+            format_timestamp(951_868_799, 999)
+        );
+        assert_eq!(
+            "2019-10-01T12:30:05.042Z",
+            format_timestamp(1_569_933_005, 42)
+        );
+    }
+
+    #[test]
+    fn request_scope() {
+        assert_eq!(None, request_id());
+        {
+            let _outer = enter_request(1);
+            {
+                let _inner = enter_request(2);
+                assert_eq!(Some(2), request_id());
+            }
+            assert_eq!(Some(1), request_id());
+        }
+        assert_eq!(None, request_id());
+
+        let mut future = WithRequestId::new(7, futures::future::lazy(|| Ok::<_, ()>(request_id())));
+        assert_eq!(Ok(futures::Async::Ready(Some(7))), future.poll());
+        assert_eq!(None, request_id());
+    }
+}
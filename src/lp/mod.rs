@@ -1,14 +1,21 @@
+use log::{error, warn};
 use lp_modeler::operations::LpOperations;
 use lp_modeler::problem::{LpObjective, LpProblem};
 use lp_modeler::solvers::{GlpkSolver, SolverTrait};
 use lp_modeler::variables::{lp_sum, LpContinuous, LpExpression};
 
 use crate::config::get_config;
+use crate::graph::learning::LearningObserver;
 use crate::graph::path::Path;
 use crate::graph::Graph;
 use crate::helpers::{costs_by_alpha, Preference};
+use crate::metrics::metrics;
 use crate::EDGE_COST_DIMENSION;
 
+pub mod partition;
+mod polytope;
+pub mod sensitivity;
+
 pub struct PreferenceEstimator<'a> {
     graph: &'a Graph,
     problem: LpProblem,
@@ -68,29 +75,50 @@ impl<'a> PreferenceEstimator<'a> {
         path: &Path,
         source_idx: usize,
         target_idx: usize,
+    ) -> Option<Preference> {
+        self.calc_preference_observed(path, source_idx, target_idx, &mut ())
+    }
+
+    /// Like calc_preference, but reports every solved LP to the observer
+    pub fn calc_preference_observed(
+        &mut self,
+        path: &Path,
+        source_idx: usize,
+        target_idx: usize,
+        observer: &mut dyn LearningObserver,
     ) -> Option<Preference> {
         let costs = path.get_subpath_costs(self.graph, source_idx, target_idx);
 
         let mut alpha = [1.0 / EDGE_COST_DIMENSION as f64; EDGE_COST_DIMENSION];
-        loop {
-            let result = self
-                .graph
-                .find_shortest_path(
-                    0,
-                    vec![path.nodes[source_idx], path.nodes[target_idx]],
-                    alpha,
-                )
-                .unwrap();
+        let mut iterations = 0;
+        let result = loop {
+            let result = match self.graph.find_shortest_path(
+                0,
+                vec![path.nodes[source_idx], path.nodes[target_idx]],
+                alpha,
+            ) {
+                Some(result) => result,
+                None => {
+                    // the path does not belong to the graph, e.g. after a reload
+                    warn!(
+                        "No route from node {} to node {} of the path for alpha {:?}",
+                        path.nodes[source_idx], path.nodes[target_idx], alpha
+                    );
+                    break None;
+                }
+            };
             if &path.nodes[source_idx..=target_idx] == result.nodes.as_slice() {
                 // Catch case paths are equal, but have slightly different costs (precision issue)
-                return Some(alpha);
+                break Some(alpha);
             } else if result.user_split.get_total_cost() > costs_by_alpha(costs, alpha) {
-                println!(
-                    "Shouldn't happen: result: {:?}; user: {:?}",
+                warn!(
+                    "Shortest path is more expensive than the user path ({:?} > {:?}) for alpha {:?}, dimension costs {:?} and {:?}",
                     result.user_split.get_total_cost(),
-                    costs_by_alpha(costs, alpha)
+                    costs_by_alpha(costs, alpha),
+                    alpha,
+                    result.total_dimension_costs,
+                    costs
                 );
-                dbg!(&costs, &result.total_dimension_costs, &alpha);
             }
             let new_delta = LpContinuous::new(&format!("delta{}", self.deltas.len()));
             self.problem += new_delta.ge(0);
@@ -102,17 +130,23 @@ impl<'a> PreferenceEstimator<'a> {
                         * ((costs[index] - result.total_dimension_costs[index]) as f32)
                 })
                 .le(0);
+            metrics().lp_cuts.inc();
 
-            match self.solve_lp() {
+            iterations += 1;
+            let solution = self.solve_lp();
+            observer.lp_solved(solution.as_ref());
+            match solution {
                 Some(result) => {
                     if result == alpha {
-                        return Some(alpha);
+                        break Some(alpha);
                     }
                     alpha = result;
                 }
-                None => return None,
+                None => break None,
             }
-        }
+        };
+        metrics().lp_iterations.observe(f64::from(iterations));
+        result
     }
 
     /*
@@ -156,6 +190,7 @@ impl<'a> PreferenceEstimator<'a> {
             .write_lp("lp_formulation")
             .expect("Could not write LP to file");
         */
+        metrics().lp_solves.inc();
         match self.solver.run(&self.problem) {
             Ok((_status, var_values)) => {
                 // println!("Solver Status: {:?}", status);
@@ -182,7 +217,8 @@ impl<'a> PreferenceEstimator<'a> {
                 Some(alpha)
             }
             Err(msg) => {
-                println!("LpError: {}", msg);
+                metrics().lp_failures.inc();
+                error!("LP solver failed: {}", msg);
                 None
             }
         }
@@ -192,4 +228,17 @@ impl<'a> PreferenceEstimator<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::graph::parse_graph_file;
+
+    #[test]
+    fn unreachable_subpath() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        // node 0 has no outgoing edges, so no route leads back from it
+        let mut path = graph
+            .find_shortest_path(0, vec![2, 0], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        path.nodes.reverse();
+        let mut estimator = PreferenceEstimator::new(&graph);
+        assert_eq!(None, estimator.calc_preference(&path, 0, 1));
+    }
 }
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+
+use crate::graph::path::Path;
+use crate::graph::Graph;
+use crate::helpers::{costs_by_alpha, Costs, Preference};
+use crate::EDGE_COST_DIMENSION;
+
+use super::polytope::{self, alphas_equal, costs_equal, EPSILON};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PreferenceRegion {
+    pub path: Path,
+    // corners of the polytope of preferences that select the path
+    pub vertices: Vec<Preference>,
+    // mean of the vertices, lies in the interior of the polytope
+    pub center: Preference,
+}
+
+/// Partitions the preference simplex for a source/target pair into regions
+/// that share the same optimal route.
+///
+/// Every region is the set of alphas for which its route is not more expensive
+/// than any other known route. The vertices of all regions are probed with
+/// Dijkstra until no probe reveals a cheaper route. Since the optimal cost is
+/// concave in alpha, the partition is exact at that point.
+pub struct PreferencePartition<'a> {
+    graph: &'a Graph,
+    paths: Vec<Path>,
+    probed: Vec<Preference>,
+}
+
+impl<'a> PreferencePartition<'a> {
+    pub fn new(graph: &'a Graph) -> Self {
+        PreferencePartition {
+            graph,
+            paths: Vec::new(),
+            probed: Vec::new(),
+        }
+    }
+
+    pub fn calc_partition(
+        &mut self,
+        source: usize,
+        target: usize,
+    ) -> Option<Vec<PreferenceRegion>> {
+        // start with the corners and the center of the simplex
+        let mut probes: Vec<Preference> = (0..EDGE_COST_DIMENSION)
+            .map(|index| {
+                let mut alpha = [0.0; EDGE_COST_DIMENSION];
+                alpha[index] = 1.0;
+                alpha
+            })
+            .collect();
+        probes.push([1.0 / EDGE_COST_DIMENSION as f64; EDGE_COST_DIMENSION]);
+
+        while !probes.is_empty() {
+            for alpha in probes.drain(..) {
+                self.probed.push(alpha);
+                let path = self
+                    .graph
+                    .find_shortest_path(0, vec![source, target], alpha)?;
+                let known = self.paths.iter().any(|other| {
+                    costs_equal(&other.total_dimension_costs, &path.total_dimension_costs)
+                });
+                if !known {
+                    self.paths.push(path);
+                }
+            }
+            for index in 0..self.paths.len() {
+                for vertex in self.region_vertices(index) {
+                    let probed = self
+                        .probed
+                        .iter()
+                        .chain(probes.iter())
+                        .any(|alpha| alphas_equal(alpha, &vertex));
+                    if !probed {
+                        probes.push(vertex);
+                    }
+                }
+            }
+        }
+
+        let mut regions = Vec::new();
+        for index in 0..self.paths.len() {
+            let vertices = self.region_vertices(index);
+            if vertices.is_empty() {
+                continue;
+            }
+            let center = polytope::centroid(&vertices);
+            // regions without interior only contain ties with other routes
+            let degenerate = self
+                .region_constraints(index)
+                .iter()
+                .any(|normal| costs_by_alpha(*normal, center) > -EPSILON);
+            if degenerate {
+                continue;
+            }
+            regions.push(PreferenceRegion {
+                path: self.paths[index].clone(),
+                vertices,
+                center,
+            });
+        }
+        Some(regions)
+    }
+
+    fn region_vertices(&self, index: usize) -> Vec<Preference> {
+        polytope::region_vertices(&self.region_constraints(index))
+    }
+
+    fn region_constraints(&self, index: usize) -> Vec<Costs> {
+        let other_costs: Vec<Costs> = self
+            .paths
+            .iter()
+            .enumerate()
+            .filter(|(other_index, _)| *other_index != index)
+            .map(|(_, other)| other.total_dimension_costs)
+            .collect();
+        polytope::region_constraints(self.paths[index].total_dimension_costs, &other_costs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::parse_graph_file;
+
+    #[test]
+    fn test_partition() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let mut partition = PreferencePartition::new(&graph);
+        let regions = partition.calc_partition(2, 10).unwrap();
+        assert_eq!(2, regions.len());
+
+        let mut edges: Vec<Vec<usize>> = regions
+            .iter()
+            .map(|region| region.path.edges.clone())
+            .collect();
+        edges.sort();
+        assert_eq!(vec![vec![4, 7, 9, 12], vec![5, 9, 12]], edges);
+
+        for region in regions {
+            let path = graph
+                .find_shortest_path(0, vec![2, 10], region.center)
+                .unwrap();
+            assert_eq!(region.path.edges, path.edges);
+        }
+    }
+
+    #[test]
+    fn no_route() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let mut partition = PreferencePartition::new(&graph);
+        assert!(partition.calc_partition(0, 4).is_none());
+    }
+}
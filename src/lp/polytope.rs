@@ -0,0 +1,146 @@
+use crate::helpers::{costs_by_alpha, Costs, Preference};
+use crate::EDGE_COST_DIMENSION;
+
+pub const EPSILON: f64 = 1e-6;
+
+/// Half spaces `normal * alpha <= 0` of all preferences for which a route with
+/// `own_costs` is not more expensive than any route with one of `other_costs`
+pub fn region_constraints(own_costs: Costs, other_costs: &[Costs]) -> Vec<Costs> {
+    let mut constraints = Vec::new();
+    for dim in 0..EDGE_COST_DIMENSION {
+        let mut normal = [0.0; EDGE_COST_DIMENSION];
+        normal[dim] = -1.0;
+        constraints.push(normal);
+    }
+    for costs in other_costs {
+        let mut normal = [0.0; EDGE_COST_DIMENSION];
+        for dim in 0..EDGE_COST_DIMENSION {
+            normal[dim] = own_costs[dim] - costs[dim];
+        }
+        let max_abs = normal.iter().fold(0.0, |acc: f64, val| acc.max(val.abs()));
+        if max_abs == 0.0 {
+            continue;
+        }
+        normal.iter_mut().for_each(|val| *val /= max_abs);
+        constraints.push(normal);
+    }
+    constraints
+}
+
+/// Vertices of the polytope given by the constraints within the preference simplex
+pub fn region_vertices(constraints: &[Costs]) -> Vec<Preference> {
+    let mut vertices: Vec<Preference> = Vec::new();
+    for active in combinations(constraints.len(), EDGE_COST_DIMENSION - 1) {
+        let rows: Vec<Costs> = active.iter().map(|idx| constraints[*idx]).collect();
+        if let Some(vertex) = solve_vertex(&rows) {
+            let feasible = constraints
+                .iter()
+                .all(|normal| costs_by_alpha(*normal, vertex) <= EPSILON);
+            if feasible && !vertices.iter().any(|other| alphas_equal(other, &vertex)) {
+                vertices.push(vertex);
+            }
+        }
+    }
+    vertices
+}
+
+pub fn centroid(vertices: &[Preference]) -> Preference {
+    let mut center = [0.0; EDGE_COST_DIMENSION];
+    for vertex in vertices {
+        for (dim, value) in vertex.iter().enumerate() {
+            center[dim] += value / vertices.len() as f64;
+        }
+    }
+    center
+}
+
+/// Solves `normal * alpha = 0` for all given normals together with `sum(alpha) = 1`
+fn solve_vertex(normals: &[Costs]) -> Option<Preference> {
+    let mut matrix: Vec<[f64; EDGE_COST_DIMENSION + 1]> = normals
+        .iter()
+        .map(|normal| {
+            let mut row = [0.0; EDGE_COST_DIMENSION + 1];
+            row[..EDGE_COST_DIMENSION].copy_from_slice(normal);
+            row
+        })
+        .collect();
+    matrix.push([1.0; EDGE_COST_DIMENSION + 1]);
+
+    for col in 0..EDGE_COST_DIMENSION {
+        let pivot = (col..EDGE_COST_DIMENSION)
+            .max_by(|a, b| {
+                matrix[*a][col]
+                    .abs()
+                    .partial_cmp(&matrix[*b][col].abs())
+                    .unwrap()
+            })
+            .unwrap();
+        if matrix[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        let pivot_row = matrix[col];
+        for (index, row) in matrix.iter_mut().enumerate() {
+            if index != col {
+                let factor = row[col] / pivot_row[col];
+                for (value, pivot_value) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+
+    let mut alpha = [0.0; EDGE_COST_DIMENSION];
+    for (index, value) in alpha.iter_mut().enumerate() {
+        *value = matrix[index][EDGE_COST_DIMENSION] / matrix[index][index];
+    }
+    Some(alpha)
+}
+
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![Vec::new()];
+    }
+    if n < k {
+        return Vec::new();
+    }
+    let mut result = combinations(n - 1, k);
+    for mut combination in combinations(n - 1, k - 1) {
+        combination.push(n - 1);
+        result.push(combination);
+    }
+    result
+}
+
+pub fn alphas_equal(a: &Preference, b: &Preference) -> bool {
+    a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < EPSILON)
+}
+
+pub fn costs_equal(a: &Costs, b: &Costs) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(x, y)| (x - y).abs() <= EPSILON * x.abs().max(y.abs()).max(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combinations() {
+        let result = combinations(4, 2);
+        assert_eq!(6, result.len());
+        assert!(result.contains(&vec![0, 3]));
+        assert!(result.contains(&vec![2, 3]));
+    }
+
+    #[test]
+    fn test_simplex_vertices() {
+        let vertices = region_vertices(&region_constraints([1.0; 4], &[]));
+        assert_eq!(4, vertices.len());
+        assert!(vertices
+            .iter()
+            .any(|vertex| alphas_equal(vertex, &[0.0, 0.0, 1.0, 0.0])));
+        assert!(alphas_equal(&centroid(&vertices), &[0.25; 4]));
+    }
+}
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+
+use crate::graph::path::Path;
+use crate::graph::Graph;
+use crate::helpers::{costs_by_alpha, Costs, Preference};
+use crate::EDGE_COST_DIMENSION;
+
+use super::polytope::{self, alphas_equal, costs_equal, EPSILON};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PreferenceSensitivity {
+    pub alpha: Preference,
+    // corners of the region of preferences for which the route stays optimal
+    pub vertices: Vec<Preference>,
+    // range of each component while the other components are scaled proportionally
+    pub intervals: Vec<(f64, f64)>,
+    // distance from alpha to the closest preference that changes the route,
+    // None if there is no alternative route at all
+    pub stability_radius: Option<f64>,
+}
+
+/// Determines how far a preference can move before the route it produced is no
+/// longer optimal.
+///
+/// The stability region is cut down with every cheaper route that a Dijkstra
+/// probe at one of its vertices reveals, until all vertices confirm the route.
+pub struct SensitivityAnalysis<'a> {
+    graph: &'a Graph,
+    alternatives: Vec<Costs>,
+    probed: Vec<Preference>,
+}
+
+impl<'a> SensitivityAnalysis<'a> {
+    pub fn new(graph: &'a Graph) -> Self {
+        SensitivityAnalysis {
+            graph,
+            alternatives: Vec::new(),
+            probed: Vec::new(),
+        }
+    }
+
+    pub fn calc_sensitivity(
+        &mut self,
+        path: &Path,
+        alpha: Preference,
+    ) -> Option<PreferenceSensitivity> {
+        let include = path.get_waypoint_nodes();
+        let own_costs = path.total_dimension_costs;
+
+        let mut probes = vec![alpha];
+        while !probes.is_empty() {
+            for probe in probes.drain(..) {
+                self.probed.push(probe);
+                let result = self.graph.find_shortest_path(0, include.clone(), probe)?;
+                let own_cost = costs_by_alpha(own_costs, probe);
+                let result_cost = costs_by_alpha(result.total_dimension_costs, probe);
+                if result_cost < own_cost - EPSILON * own_cost.abs().max(1.0) {
+                    if alphas_equal(&probe, &alpha) {
+                        // alpha does not select the route in the first place
+                        return None;
+                    }
+                    let known = self
+                        .alternatives
+                        .iter()
+                        .any(|costs| costs_equal(costs, &result.total_dimension_costs));
+                    if !known {
+                        self.alternatives.push(result.total_dimension_costs);
+                    }
+                }
+            }
+            let constraints = polytope::region_constraints(own_costs, &self.alternatives);
+            for vertex in polytope::region_vertices(&constraints) {
+                let probed = self
+                    .probed
+                    .iter()
+                    .chain(probes.iter())
+                    .any(|other| alphas_equal(other, &vertex));
+                if !probed {
+                    probes.push(vertex);
+                }
+            }
+        }
+
+        let constraints = polytope::region_constraints(own_costs, &self.alternatives);
+        let vertices = polytope::region_vertices(&constraints);
+        let intervals = (0..EDGE_COST_DIMENSION)
+            .map(|dim| component_interval(&constraints, alpha, dim))
+            .collect();
+
+        // the first constraints only bound the simplex and never change the route
+        let stability_radius = constraints[EDGE_COST_DIMENSION..]
+            .iter()
+            .filter_map(|normal| distance_to_hyperplane(*normal, alpha))
+            .fold(None, |acc: Option<f64>, distance| {
+                Some(acc.map_or(distance, |min| min.min(distance)))
+            });
+
+        Some(PreferenceSensitivity {
+            alpha,
+            vertices,
+            intervals,
+            stability_radius,
+        })
+    }
+}
+
+/// Range of `alpha[dim]` within the constraints when the remaining components
+/// keep their ratios to each other
+fn component_interval(constraints: &[Costs], alpha: Preference, dim: usize) -> (f64, f64) {
+    let mut rest = alpha;
+    rest[dim] = 0.0;
+    let rest_sum: f64 = rest.iter().sum();
+    for (index, value) in rest.iter_mut().enumerate() {
+        if rest_sum > 0.0 {
+            *value /= rest_sum;
+        } else if index != dim {
+            *value = 1.0 / (EDGE_COST_DIMENSION - 1) as f64;
+        }
+    }
+
+    // normal * (t * e_dim + (1 - t) * rest) = base + t * slope
+    let mut low: f64 = 0.0;
+    let mut high: f64 = 1.0;
+    for normal in constraints {
+        let base = costs_by_alpha(*normal, rest);
+        let slope = normal[dim] - base;
+        if slope > EPSILON {
+            high = high.min(-base / slope);
+        } else if slope < -EPSILON {
+            low = low.max(-base / slope);
+        }
+    }
+    (low, high)
+}
+
+/// Euclidean distance between alpha and the hyperplane `normal * alpha = 0`
+/// within the plane of preferences summing up to 1
+fn distance_to_hyperplane(normal: Costs, alpha: Preference) -> Option<f64> {
+    let mean = normal.iter().sum::<f64>() / EDGE_COST_DIMENSION as f64;
+    let length = normal
+        .iter()
+        .fold(0.0, |acc, val| acc + (val - mean).powi(2))
+        .sqrt();
+    if length < EPSILON {
+        return None;
+    }
+    Some((-costs_by_alpha(normal, alpha) / length).max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::parse_graph_file;
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < EPSILON,
+            "{} != {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_sensitivity() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let alpha = [1.0, 0.0, 0.0, 0.0];
+        let path = graph.find_shortest_path(0, vec![2, 10], alpha).unwrap();
+        let mut analysis = SensitivityAnalysis::new(&graph);
+        let result = analysis.calc_sensitivity(&path, alpha).unwrap();
+
+        assert_close(0.25, result.intervals[0].0);
+        assert_close(1.0, result.intervals[0].1);
+        assert_close(0.0, result.intervals[2].0);
+        assert_close(0.5, result.intervals[2].1);
+        assert_close(0.5, result.stability_radius.unwrap());
+    }
+
+    #[test]
+    fn alpha_not_optimal() {
+        let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+        let path = graph
+            .find_shortest_path(0, vec![2, 10], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        let mut analysis = SensitivityAnalysis::new(&graph);
+        assert!(analysis
+            .calc_sensitivity(&path, [0.0, 0.0, 1.0, 0.0])
+            .is_none());
+    }
+}
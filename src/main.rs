@@ -1,19 +1,19 @@
 use std::env;
+use std::process;
 
-mod config;
-mod graph;
-mod helpers;
-mod lp;
-mod server;
-mod user;
-
-const EDGE_COST_DIMENSION: usize = 4;
+mod cli;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Please provide exactly one parameter, which is the path to the graph file");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match cli::parse_args(&args) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(message) = cli::run(args) {
+        eprintln!("Error: {}", message);
+        process::exit(1);
     }
-    let graph = graph::parse_graph_file(&args[1]).unwrap();
-    server::start_server(graph);
 }
@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+const PREFIX: &str = "preference_routing_";
+
+const SECONDS: [f64; 10] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 30.0];
+const SEARCH_SPACE: [f64; 7] = [10.0, 100.0, 1e3, 1e4, 1e5, 1e6, 1e7];
+const LP_ITERATIONS: [f64; 7] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+const SEGMENTS: [f64; 6] = [1.0, 2.0, 3.0, 5.0, 10.0, 20.0];
+
+pub struct Counter {
+    value: AtomicU64,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Counter {
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts observations into buckets with upper bounds, the last bucket has none
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    // sum of all observations, as bits of an f64
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+    }
+
+    fn write(&self, out: &mut String, name: &str) {
+        let mut cumulative = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let bound = match self.bounds.get(index) {
+                Some(bound) => bound.to_string(),
+                None => String::from("+Inf"),
+            };
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+    }
+}
+
+/// Statistics of the whole process, shown by the server at /metrics
+pub struct Metrics {
+    pub queries: Counter,
+    pub queries_unreachable: Counter,
+    pub query_seconds: Histogram,
+    pub query_nodes_popped: Histogram,
+    pub query_edges_relaxed: Histogram,
+    pub lp_solves: Counter,
+    pub lp_failures: Counter,
+    pub lp_cuts: Counter,
+    pub lp_iterations: Histogram,
+    pub learning_runs: Counter,
+    pub learning_failures: Counter,
+    pub learning_seconds: Histogram,
+    pub learning_segments: Histogram,
+    // (method, status) of the responses
+    http_requests: Mutex<BTreeMap<(String, u16), u64>>,
+    pub http_seconds: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Metrics {
+            queries: Counter::new(),
+            queries_unreachable: Counter::new(),
+            query_seconds: Histogram::new(&SECONDS),
+            query_nodes_popped: Histogram::new(&SEARCH_SPACE),
+            query_edges_relaxed: Histogram::new(&SEARCH_SPACE),
+            lp_solves: Counter::new(),
+            lp_failures: Counter::new(),
+            lp_cuts: Counter::new(),
+            lp_iterations: Histogram::new(&LP_ITERATIONS),
+            learning_runs: Counter::new(),
+            learning_failures: Counter::new(),
+            learning_seconds: Histogram::new(&SECONDS),
+            learning_segments: Histogram::new(&SEGMENTS),
+            http_requests: Mutex::new(BTreeMap::new()),
+            http_seconds: Histogram::new(&SECONDS),
+        }
+    }
+
+    pub fn count_http_request(&self, method: &str, status: u16) {
+        let mut requests = self.http_requests.lock().unwrap();
+        *requests.entry((String::from(method), status)).or_insert(0) += 1;
+    }
+
+    /// All metrics in the text format of Prometheus
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "queries_total",
+                "Shortest path queries, one per leg",
+                &self.queries,
+            ),
+            (
+                "queries_unreachable_total",
+                "Queries whose target could not be reached",
+                &self.queries_unreachable,
+            ),
+            ("lp_solves_total", "Runs of the LP solver", &self.lp_solves),
+            (
+                "lp_failures_total",
+                "Failed runs of the LP solver",
+                &self.lp_failures,
+            ),
+            (
+                "lp_cuts_total",
+                "Constraints added to the LP for cheaper routes",
+                &self.lp_cuts,
+            ),
+            (
+                "learning_runs_total",
+                "Paths whose preferences were searched",
+                &self.learning_runs,
+            ),
+            (
+                "learning_failures_total",
+                "Paths no preference could be found for",
+                &self.learning_failures,
+            ),
+        ];
+        for (name, help, counter) in counters.iter() {
+            write_header(&mut out, name, help, "counter");
+            let _ = writeln!(out, "{}{} {}", PREFIX, name, counter.get());
+        }
+
+        let histograms = [
+            ("query_seconds", "Duration of a query", &self.query_seconds),
+            (
+                "query_nodes_popped",
+                "Nodes taken from the queue per query",
+                &self.query_nodes_popped,
+            ),
+            (
+                "query_edges_relaxed",
+                "Edges relaxed per query",
+                &self.query_edges_relaxed,
+            ),
+            (
+                "lp_iterations",
+                "LP solves needed to explain a part of a path",
+                &self.lp_iterations,
+            ),
+            (
+                "learning_seconds",
+                "Duration of the preference search of a path",
+                &self.learning_seconds,
+            ),
+            (
+                "learning_segments",
+                "Parts with their own preference per path",
+                &self.learning_segments,
+            ),
+            (
+                "http_request_seconds",
+                "Duration of a request",
+                &self.http_seconds,
+            ),
+        ];
+        for (name, help, histogram) in histograms.iter() {
+            write_header(&mut out, name, help, "histogram");
+            histogram.write(&mut out, &format!("{}{}", PREFIX, name));
+        }
+
+        write_header(
+            &mut out,
+            "http_requests_total",
+            "Answered requests",
+            "counter",
+        );
+        for ((method, status), count) in self.http_requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "{}http_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                PREFIX, method, status, count
+            );
+        }
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {}{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}{} {}", PREFIX, name, kind);
+}
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        static BOUNDS: [f64; 3] = [1.0, 2.0, 5.0];
+        let histogram = Histogram::new(&BOUNDS);
+        for value in &[1.0, 1.5, 4.0, 7.0] {
+            histogram.observe(*value);
+        }
+        let mut out = String::new();
+        histogram.write(&mut out, "test");
+        assert_eq!(
+            "test_bucket{le=\"1\"} 1\n\
+             test_bucket{le=\"2\"} 2\n\
+             test_bucket{le=\"5\"} 3\n\
+             test_bucket{le=\"+Inf\"} 4\n\
+             test_sum 13.5\n\
+             test_count 4\n",
+            out
+        );
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.queries.inc();
+        metrics.query_nodes_popped.observe(42.0);
+        metrics.count_http_request("GET", 200);
+        metrics.count_http_request("GET", 200);
+        let out = metrics.render();
+        assert!(out.contains("# TYPE preference_routing_queries_total counter\n"));
+        assert!(out.contains("\npreference_routing_queries_total 1\n"));
+        assert!(out.contains("\npreference_routing_query_nodes_popped_bucket{le=\"100\"} 1\n"));
+        assert!(out.contains(
+            "\npreference_routing_http_requests_total{method=\"GET\",status=\"200\"} 2\n"
+        ));
+    }
+}
@@ -0,0 +1,476 @@
+use actix_web::http::{Method, StatusCode};
+use actix_web::web;
+use serde_json::json;
+
+use crate::graph::customize::EdgeUpdate;
+use crate::graph::path::Path;
+use crate::helpers::{Coordinate, Preference};
+use crate::lp::partition::PreferenceRegion;
+use crate::lp::sensitivity::PreferenceSensitivity;
+
+use super::auth::{self, Credentials};
+use super::error::invalid_request;
+use super::jobs::JobInfo;
+use super::openapi::{self, Endpoint};
+use super::routing::{
+    self, ClosestQuery, ConstrainedRequest, ConstrainedResponse, EdgeUpdateResponse, FspRequest,
+    GraphInfo, GraphQuery, PartitionRequest, RouteRequest, SensitivityRequest,
+};
+use super::v2::{self, EventsQuery, RoutePatch};
+
+/// Registers the extractor configs and the route of every endpoint
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .data(web::JsonConfig::default().error_handler(|err, _| invalid_request(err)))
+        .data(web::QueryConfig::default().error_handler(|err, _| invalid_request(err)))
+        .data(web::PathConfig::default().error_handler(|err, _| invalid_request(err)));
+    for endpoint in endpoints() {
+        config.route(
+            endpoint.path,
+            (endpoint.route)(web::method(endpoint.method)),
+        );
+    }
+}
+
+/// Every endpoint of the server, the routes and the OpenAPI document are
+/// built from this list
+pub fn endpoints() -> Vec<Endpoint> {
+    vec![
+        Endpoint::new("get_openapi", Method::GET, "/openapi.json", |route| {
+            route.to(openapi::get_openapi)
+        })
+        .summary("This document")
+        .content(
+            StatusCode::OK,
+            "application/json",
+            json!({ "type": "object" }),
+        ),
+        // routing stuff
+        Endpoint::new("get_metrics", Method::GET, "/metrics", |route| {
+            route.to(routing::get_metrics)
+        })
+        .summary("Metrics in the Prometheus text format")
+        .content(StatusCode::OK, "text/plain", json!({ "type": "string" })),
+        Endpoint::new("get_cost_tags", Method::GET, "/tags", |route| {
+            route.to(routing::get_cost_tags)
+        })
+        .summary("Names of the cost dimensions, in the order of alphas and costs")
+        .response::<Vec<String>>(StatusCode::OK),
+        Endpoint::new("get_graphs", Method::GET, "/graphs", |route| {
+            route.to(routing::get_graphs)
+        })
+        .summary("Graphs and the areas they cover")
+        .response::<Vec<GraphInfo>>(StatusCode::OK),
+        Endpoint::new(
+            "reload_graph",
+            Method::POST,
+            "/graphs/{name}/reload",
+            |route| route.to(routing::reload_graph),
+        )
+        .summary("Starts to reload the graph from its file")
+        .admin()
+        .param::<String>("name")
+        .empty(StatusCode::ACCEPTED),
+        Endpoint::new("get_preference", Method::GET, "/preference", |route| {
+            route.to(routing::get_preference)
+        })
+        .summary("Preferences of the user")
+        .authorized()
+        .response::<Vec<Preference>>(StatusCode::OK),
+        Endpoint::new("set_preference", Method::POST, "/preference", |route| {
+            route.to(routing::set_preference)
+        })
+        .summary("Replaces the preferences of the user")
+        .authorized()
+        .body::<Vec<Preference>>()
+        .empty(StatusCode::OK),
+        Endpoint::new("new_preference", Method::POST, "/preference/new", |route| {
+            route.to(routing::new_preference)
+        })
+        .summary("Adds the initial preference to the preferences of the user")
+        .authorized()
+        .response::<Vec<Preference>>(StatusCode::OK),
+        Endpoint::new(
+            "find_preference",
+            Method::POST,
+            "/preference/find",
+            |route| route.to(routing::find_preference),
+        )
+        .summary("Finds a route, learns its preferences and stores it")
+        .authorized()
+        .body::<FspRequest>()
+        .response::<Vec<Path>>(StatusCode::OK),
+        Endpoint::new(
+            "find_partition",
+            Method::POST,
+            "/preference/partition",
+            |route| route.to(routing::find_partition),
+        )
+        .summary("Regions of preferences that share the same route")
+        .body::<PartitionRequest>()
+        .response::<Vec<PreferenceRegion>>(StatusCode::OK),
+        Endpoint::new(
+            "find_sensitivity",
+            Method::POST,
+            "/preference/sensitivity",
+            |route| route.to(routing::find_sensitivity),
+        )
+        .summary("How far the preference can change before the route changes")
+        .body::<SensitivityRequest>()
+        .response::<PreferenceSensitivity>(StatusCode::OK),
+        Endpoint::new("find_closest", Method::GET, "/closest", |route| {
+            route.to(routing::find_closest)
+        })
+        .summary("Location of the node closest to the coordinate")
+        .query::<ClosestQuery>()
+        .response::<Coordinate>(StatusCode::OK),
+        Endpoint::new("fsp", Method::POST, "/fsp", |route| route.to(routing::fsp))
+            .summary("Finds a route, a non-zero id replaces the stored route")
+            .authorized()
+            .body::<FspRequest>()
+            .response::<Path>(StatusCode::OK),
+        Endpoint::new(
+            "constrained_fsp",
+            Method::POST,
+            "/fsp/constrained",
+            |route| route.to(routing::constrained_fsp),
+        )
+        .summary("Finds the best route within the budgets")
+        .body::<ConstrainedRequest>()
+        .response::<ConstrainedResponse>(StatusCode::OK),
+        Endpoint::new("update_edge_costs", Method::POST, "/edges/costs", |route| {
+            route.to(routing::update_edge_costs)
+        })
+        .summary("Sets the costs of edges of the graph")
+        .admin()
+        .query::<GraphQuery>()
+        .body::<Vec<EdgeUpdate>>()
+        .response::<EdgeUpdateResponse>(StatusCode::OK),
+        Endpoint::new("get_routes", Method::GET, "/routes", |route| {
+            route.to(routing::get_routes)
+        })
+        .summary("Stored routes of the user")
+        .authorized()
+        .response::<Vec<Path>>(StatusCode::OK),
+        Endpoint::new(
+            "delete_stored_route",
+            Method::POST,
+            "/delete/{id}",
+            |route| route.to(routing::delete_route),
+        )
+        .summary("Deletes the stored route if it exists")
+        .authorized()
+        .param::<usize>("id")
+        .response::<Vec<Path>>(StatusCode::OK),
+        Endpoint::new("reset_data", Method::POST, "/reset", |route| {
+            route.to(routing::reset_data)
+        })
+        .summary("Deletes the routes and preferences of the user")
+        .authorized()
+        .empty(StatusCode::OK),
+        Endpoint::new("list_routes", Method::GET, "/v2/routes", |route| {
+            route.to(v2::list_routes)
+        })
+        .summary("Stored routes of the user")
+        .authorized()
+        .response::<Vec<Path>>(StatusCode::OK),
+        Endpoint::new("create_route", Method::POST, "/v2/routes", |route| {
+            route.to(v2::create_route)
+        })
+        .summary("Finds a route and stores it")
+        .authorized()
+        .body::<RouteRequest>()
+        .response::<Path>(StatusCode::CREATED),
+        Endpoint::new("get_route", Method::GET, "/v2/routes/{id}", |route| {
+            route.to(v2::get_route)
+        })
+        .summary("Stored route")
+        .authorized()
+        .param::<usize>("id")
+        .response::<Path>(StatusCode::OK),
+        Endpoint::new("replace_route", Method::PUT, "/v2/routes/{id}", |route| {
+            route.to(v2::replace_route)
+        })
+        .summary("Finds the stored route again, which drops learned preferences")
+        .authorized()
+        .param::<usize>("id")
+        .body::<RouteRequest>()
+        .response::<Path>(StatusCode::OK),
+        Endpoint::new("update_route", Method::PATCH, "/v2/routes/{id}", |route| {
+            route.to(v2::update_route)
+        })
+        .summary("Finds the stored route again with some of its fields changed")
+        .authorized()
+        .param::<usize>("id")
+        .body::<RoutePatch>()
+        .response::<Path>(StatusCode::OK),
+        Endpoint::new("delete_route", Method::DELETE, "/v2/routes/{id}", |route| {
+            route.to(v2::delete_route)
+        })
+        .summary("Deletes the stored route")
+        .authorized()
+        .param::<usize>("id")
+        .empty(StatusCode::NO_CONTENT),
+        Endpoint::new("learn", Method::POST, "/v2/routes/{id}/learn", |route| {
+            route.to(v2::learn)
+        })
+        .summary("Queues a job that learns the preferences of the stored route")
+        .authorized()
+        .param::<usize>("id")
+        .response::<JobInfo>(StatusCode::ACCEPTED),
+        Endpoint::new("list_jobs", Method::GET, "/v2/jobs", |route| {
+            route.to(v2::list_jobs)
+        })
+        .summary("Jobs of the user")
+        .authorized()
+        .response::<Vec<JobInfo>>(StatusCode::OK),
+        Endpoint::new("get_job", Method::GET, "/v2/jobs/{id}", |route| {
+            route.to(v2::get_job)
+        })
+        .summary("Job of the user")
+        .authorized()
+        .param::<usize>("id")
+        .response::<JobInfo>(StatusCode::OK),
+        Endpoint::new("cancel_job", Method::DELETE, "/v2/jobs/{id}", |route| {
+            route.to(v2::cancel_job)
+        })
+        .summary("Cancels the job")
+        .authorized()
+        .param::<usize>("id")
+        .response::<JobInfo>(StatusCode::OK),
+        Endpoint::new("job_events", Method::GET, "/v2/jobs/{id}/events", |route| {
+            route.to(v2::job_events)
+        })
+        .summary("WebSocket that sends the events of the job as JSON text frames")
+        .authorized()
+        .param::<usize>("id")
+        .query::<EventsQuery>()
+        .empty(StatusCode::SWITCHING_PROTOCOLS),
+        // auth stuff
+        Endpoint::new("login", Method::POST, "/login", |route| {
+            route.to(auth::login)
+        })
+        .summary("Token of the user")
+        .body::<Credentials>()
+        .response::<String>(StatusCode::OK),
+        Endpoint::new("register", Method::POST, "/register", |route| {
+            route.to(auth::register)
+        })
+        .summary("Adds a user")
+        .body::<Credentials>()
+        .empty(StatusCode::OK),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+    use serde_json::{Map, Value};
+
+    use super::*;
+    use crate::graph::load_graph;
+    use crate::server::error::{ApiError, ErrorCode};
+    use crate::server::openapi::document;
+    use crate::server::tests::Database;
+    use crate::server::AppState;
+
+    // a value the schema allows, with every property and the shortest arrays
+    fn example(schema: &Value) -> Value {
+        match schema["type"].as_str() {
+            Some("object") => {
+                let properties = schema["properties"].as_object().unwrap();
+                properties
+                    .iter()
+                    .map(|(name, property)| (name.clone(), example(property)))
+                    .collect::<Map<String, Value>>()
+                    .into()
+            }
+            Some("array") => {
+                let len = schema["minItems"].as_u64().unwrap_or(1) as usize;
+                Value::Array(vec![example(&schema["items"]); len])
+            }
+            Some("string") => schema["enum"]
+                .get(0)
+                .cloned()
+                .unwrap_or_else(|| json!("example")),
+            Some("number") | Some("integer") => json!(0),
+            Some("boolean") => json!(false),
+            _ => panic!("No example for {}", schema),
+        }
+    }
+
+    // panics if the value does not match the schema, which has no unknown
+    // properties
+    fn check(schema: &Value, value: &Value, at: &str) {
+        if value.is_null() && schema["nullable"] == json!(true) {
+            return;
+        }
+        let matches = match (schema["type"].as_str(), value) {
+            (Some("object"), Value::Object(object)) => {
+                // objects without properties are not described any further
+                if let Some(properties) = schema["properties"].as_object() {
+                    for (name, field) in object {
+                        let property = properties
+                            .get(name)
+                            .unwrap_or_else(|| panic!("{}.{} is not documented", at, name));
+                        check(property, field, &format!("{}.{}", at, name));
+                    }
+                }
+                let required = schema["required"].as_array().cloned().unwrap_or_default();
+                for name in required {
+                    assert!(
+                        object.contains_key(name.as_str().unwrap()),
+                        "{}.{} is missing",
+                        at,
+                        name
+                    );
+                }
+                true
+            }
+            (Some("array"), Value::Array(items)) => {
+                for (index, item) in items.iter().enumerate() {
+                    check(&schema["items"], item, &format!("{}[{}]", at, index));
+                }
+                let len = items.len() as u64;
+                let min = schema["minItems"].as_u64().unwrap_or(0);
+                let max = schema["maxItems"].as_u64().unwrap_or(u64::MAX);
+                min <= len && len <= max
+            }
+            (Some("string"), Value::String(_)) => match schema["enum"].as_array() {
+                Some(variants) => variants.contains(value),
+                None => true,
+            },
+            (Some("number"), _) => value.is_number(),
+            (Some("integer"), _) => value.is_u64() || value.is_i64(),
+            (Some("boolean"), _) => value.is_boolean(),
+            (Some(_), _) => false,
+            (None, _) => panic!("Unknown schema {} at {}", schema, at),
+        };
+        assert!(matches, "{} is {} but must match {}", at, value, schema);
+    }
+
+    #[test]
+    fn paths() {
+        let endpoints = endpoints();
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            for other in &endpoints[..index] {
+                assert_ne!(endpoint.id, other.id);
+                assert!(endpoint.path != other.path || endpoint.method != other.method);
+            }
+        }
+        let document = document(&endpoints);
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let mut documented: Vec<&str> = operation["parameters"]
+                    .as_array()
+                    .map(|parameters| {
+                        parameters
+                            .iter()
+                            .filter(|parameter| parameter["in"] == json!("path"))
+                            .map(|parameter| parameter["name"].as_str().unwrap())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let mut segments: Vec<&str> = path
+                    .split('/')
+                    .filter(|segment| segment.starts_with('{'))
+                    .map(|segment| segment.trim_matches(|c| c == '{' || c == '}'))
+                    .collect();
+                documented.sort();
+                segments.sort();
+                assert_eq!(segments, documented, "{} {}", method, path);
+            }
+        }
+    }
+
+    // Sends a request built from the document to every operation. The handler
+    // must accept it and answer with a documented response or an error of the
+    // handler itself.
+    #[test]
+    fn handlers_match_document() {
+        let document = document(&endpoints());
+        for (path, item) in document["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                let graph = load_graph("default", "./src/test_graphs/testGraph").unwrap();
+                let route = graph
+                    .find_shortest_path(0, vec![2, 10], [1.0, 0.0, 0.0, 0.0])
+                    .unwrap();
+                let database = Database::new("api-document");
+                let mut state = AppState::new(vec![graph], &database.0);
+                state.admin_token = Some(String::from("admin"));
+                let token = {
+                    let mut users = state.users.lock().unwrap();
+                    users[0].add_route(&mut route.clone());
+                    users[0].auth.token.clone()
+                };
+                state.jobs.submit("test", route).unwrap();
+                let mut app = test::init_service(
+                    App::new()
+                        .register_data(web::Data::new(state))
+                        .configure(configure),
+                );
+
+                let mut uri = path.clone();
+                let mut query = Vec::new();
+                let parameters = operation["parameters"].as_array().cloned();
+                for parameter in parameters.unwrap_or_default() {
+                    let value = example(&parameter["schema"]).to_string();
+                    let value = value.trim_matches('"');
+                    let name = parameter["name"].as_str().unwrap();
+                    if parameter["in"] == json!("path") {
+                        uri = uri.replace(&format!("{{{}}}", name), value);
+                    } else if parameter["required"] == json!(true) {
+                        query.push(format!("{}={}", name, value));
+                    }
+                }
+                if !query.is_empty() {
+                    uri = format!("{}?{}", uri, query.join("&"));
+                }
+                let token = if operation["security"] == json!([{ "admin": [] }]) {
+                    String::from("admin")
+                } else {
+                    token
+                };
+                let mut request = TestRequest::default()
+                    .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                    .uri(&uri)
+                    .header("Authorization", token);
+                let body = &operation["requestBody"]["content"]["application/json"]["schema"];
+                if !body.is_null() {
+                    request = request.set_json(&example(body));
+                }
+
+                let response = test::call_service(&mut app, request.to_request());
+                let status = response.status();
+                let content_type = response
+                    .headers()
+                    .get("content-type")
+                    .map(|value| String::from(value.to_str().unwrap()));
+                let body = test::read_body(response);
+                let at = format!("{} {}", method, uri);
+                match operation["responses"].get(status.as_str()) {
+                    Some(documented) => match documented["content"].as_object() {
+                        Some(content) => {
+                            let (expected_type, media) = content.iter().next().unwrap();
+                            let content_type = content_type.unwrap_or_default();
+                            assert!(content_type.starts_with(expected_type.as_str()), "{}", at);
+                            if expected_type == "application/json" {
+                                let value: Value = serde_json::from_slice(&body).unwrap();
+                                check(&media["schema"], &value, &at);
+                            }
+                        }
+                        None => assert!(body.is_empty(), "{}", at),
+                    },
+                    None => {
+                        let error: ApiError = serde_json::from_slice(&body).unwrap_or_else(|_| {
+                            panic!("{} answered {} without an error", at, status)
+                        });
+                        assert_ne!(ErrorCode::InvalidRequest, error.code, "{}: {}", at, error);
+                        assert_eq!(error.code.status(), status, "{}", at);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use actix_web::{web, HttpResponse};
+use log::info;
 use serde::Deserialize;
 
+use super::error::{ApiError, ErrorCode};
 use super::AppState;
 use crate::user::UserState;
 
@@ -10,30 +12,42 @@ pub struct Credentials {
     password: String,
 }
 
-pub fn login(state: web::Data<AppState>, body: web::Json<Credentials>) -> HttpResponse {
+pub fn login(
+    state: web::Data<AppState>,
+    body: web::Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
     let Credentials { username, password } = body.into_inner();
-    let mut users = state.users.lock().unwrap();
+    let users = state.users.lock()?;
     let user_state = users
-        .iter_mut()
+        .iter()
         .find(|x| x.auth.credentials_valid(&username, &password));
     match user_state {
-        None => HttpResponse::Unauthorized().finish(),
+        None => Err(ApiError::new(
+            ErrorCode::Unauthorized,
+            "Unknown username or wrong password",
+        )),
         Some(state) => {
-            println!("Login user {}", username);
-            HttpResponse::Ok().json(&state.auth.token)
+            info!("Login user {}", username);
+            Ok(HttpResponse::Ok().json(&state.auth.token))
         }
     }
 }
 
-pub fn register(state: web::Data<AppState>, body: web::Json<Credentials>) -> HttpResponse {
+pub fn register(
+    state: web::Data<AppState>,
+    body: web::Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
     let Credentials { username, password } = body.into_inner();
-    let mut users = state.users.lock().unwrap();
+    let mut users = state.users.lock()?;
     let username_taken = users.iter().any(|x| x.auth.username == username);
     if username_taken {
-        return HttpResponse::Unauthorized().finish();
+        return Err(ApiError::new(
+            ErrorCode::UsernameTaken,
+            format!("The username {} is taken", username),
+        ));
     }
-    println!("Register user {}", username);
+    info!("Register user {}", username);
     let new_user = UserState::new(username, password);
     users.push(new_user);
-    HttpResponse::Ok().finish()
+    Ok(HttpResponse::Ok().finish())
 }
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::sync::PoisonError;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+/// Machine-readable reason of a failed request, serialized as e.g. "NO_ROUTE"
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // the body, query or path of the request can not be parsed
+    InvalidRequest,
+    InvalidWaypoints,
+    InvalidPreference,
+    InvalidEdgeUpdate,
+    // missing or unknown token, or not the admin token for administrative requests
+    Unauthorized,
+    UsernameTaken,
+    UnknownGraph,
+    RouteNotFound,
+    JobNotFound,
+    // the route was replaced or deleted while a job learned it
+    RouteChanged,
+    // the graph of the route was replaced and the route is not part of the new one
+    RouteStale,
+    NoRoute,
+    LearningFailed,
+    ReloadInProgress,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidWaypoints
+            | ErrorCode::InvalidPreference
+            | ErrorCode::InvalidEdgeUpdate => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::UnknownGraph
+            | ErrorCode::RouteNotFound
+            | ErrorCode::JobNotFound
+            | ErrorCode::NoRoute => StatusCode::NOT_FOUND,
+            ErrorCode::UsernameTaken
+            | ErrorCode::RouteStale
+            | ErrorCode::RouteChanged
+            | ErrorCode::ReloadInProgress => StatusCode::CONFLICT,
+            ErrorCode::LearningFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Error of every handler, answered as {"code": ..., "message": ...} with the
+/// status of the code
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ApiError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        ApiError::new(
+            ErrorCode::Unauthorized,
+            "A valid token is needed in the Authorization header",
+        )
+    }
+
+    pub fn not_admin() -> Self {
+        ApiError::new(
+            ErrorCode::Unauthorized,
+            "The admin token is needed in the Authorization header",
+        )
+    }
+
+    pub fn unknown_graph() -> Self {
+        ApiError::new(
+            ErrorCode::UnknownGraph,
+            "No graph covers the request, select one by its name",
+        )
+    }
+
+    pub fn route_not_found(id: usize) -> Self {
+        ApiError::new(
+            ErrorCode::RouteNotFound,
+            format!("There is no route with id {}", id),
+        )
+    }
+
+    pub fn no_route() -> Self {
+        ApiError::new(ErrorCode::NoRoute, "No route connects the waypoints")
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.code.status()).json(self)
+    }
+
+    fn render_response(&self) -> HttpResponse {
+        self.error_response()
+    }
+}
+
+// a handler panicked while holding the lock, the data may be inconsistent
+impl<T> From<PoisonError<T>> for ApiError {
+    fn from(_: PoisonError<T>) -> Self {
+        error!("Lock poisoned by an earlier panic");
+        ApiError::new(ErrorCode::Internal, "Internal server error")
+    }
+}
+
+/// Turns errors of the extractors into an INVALID_REQUEST, used by the
+/// configs of Json, Query and Path
+pub fn invalid_request<E: fmt::Display>(err: E) -> actix_web::Error {
+    ApiError::new(ErrorCode::InvalidRequest, err.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_body() {
+        let error = ApiError::new(ErrorCode::NoRoute, "No route");
+        assert_eq!(
+            r#"{"code":"NO_ROUTE","message":"No route"}"#,
+            serde_json::to_string(&error).unwrap()
+        );
+        let response = error.render_response();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(
+            "application/json",
+            response.headers().get("content-type").unwrap()
+        );
+    }
+}
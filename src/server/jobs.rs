@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::panic;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use actix_web::web;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::graph::learning::LearningObserver;
+use crate::graph::path::{Path, PathSplit};
+use crate::helpers::Preference;
+use crate::logging;
+
+use super::error::{ApiError, ErrorCode};
+use super::routing::learn_route;
+use super::AppState;
+
+// finished jobs stay queryable until this many newer ones have finished
+const KEPT_FINISHED_JOBS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    // the preferences are stored in the route
+    Finished,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    fn is_done(self) -> bool {
+        match self {
+            JobStatus::Queued | JobStatus::Running => false,
+            JobStatus::Finished | JobStatus::Failed | JobStatus::Cancelled => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Progress {
+    // nodes of the route
+    pub nodes: usize,
+    // index of the node the part that is searched next starts at
+    pub cut: usize,
+    // parts whose preference is known
+    pub segments: usize,
+}
+
+/// State of a job as answered by the API
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct JobInfo {
+    pub id: usize,
+    pub route_id: usize,
+    pub status: JobStatus,
+    pub progress: Progress,
+    pub error: Option<ApiError>,
+}
+
+/// Steps of a job as sent to its listeners. Positions are indices into the
+/// nodes of the route.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    // state of the job when the listener connects
+    Job {
+        job: JobInfo,
+    },
+    ProbeStarted {
+        start: usize,
+        end: usize,
+    },
+    LpSolved {
+        alpha: Option<Preference>,
+    },
+    ProbeFinished {
+        start: usize,
+        end: usize,
+        alpha: Option<Preference>,
+    },
+    SegmentFound {
+        start: usize,
+        end: usize,
+        alpha: Preference,
+    },
+    Finished {
+        algo_split: Option<PathSplit>,
+    },
+    Failed {
+        error: ApiError,
+    },
+    Cancelled,
+}
+
+struct Job {
+    info: JobInfo,
+    username: String,
+    // taken by the worker that runs the job
+    route: Option<Path>,
+    cancelled: bool,
+    // request that submitted the job, its messages are logged with the id
+    request_id: Option<u64>,
+    listeners: Vec<UnboundedSender<JobEvent>>,
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: usize,
+    jobs: BTreeMap<usize, Job>,
+    queued: VecDeque<usize>,
+    finished: VecDeque<usize>,
+}
+
+/// Learning jobs of all users, run by a fixed number of worker threads in the
+/// order they were submitted
+pub struct JobQueue {
+    jobs: Mutex<Jobs>,
+    available: Condvar,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        JobQueue {
+            jobs: Mutex::new(Jobs {
+                next_id: 1,
+                ..Jobs::default()
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    pub fn submit(&self, username: &str, route: Path) -> Result<JobInfo, ApiError> {
+        let mut jobs = self.jobs.lock()?;
+        let id = jobs.next_id;
+        jobs.next_id += 1;
+        let info = JobInfo {
+            id,
+            route_id: route.id,
+            status: JobStatus::Queued,
+            progress: Progress {
+                nodes: route.nodes.len(),
+                ..Progress::default()
+            },
+            error: None,
+        };
+        jobs.jobs.insert(
+            id,
+            Job {
+                info: info.clone(),
+                username: String::from(username),
+                route: Some(route),
+                cancelled: false,
+                request_id: logging::request_id(),
+                listeners: Vec::new(),
+            },
+        );
+        jobs.queued.push_back(id);
+        self.available.notify_one();
+        info!("Queued learning job {} for route {}", id, info.route_id);
+        Ok(info)
+    }
+
+    /// The job, if it belongs to the user
+    pub fn job(&self, username: &str, id: usize) -> Result<JobInfo, ApiError> {
+        let jobs = self.jobs.lock()?;
+        match jobs.jobs.get(&id) {
+            Some(job) if job.username == username => Ok(job.info.clone()),
+            _ => Err(job_not_found(id)),
+        }
+    }
+
+    pub fn jobs(&self, username: &str) -> Result<Vec<JobInfo>, ApiError> {
+        let jobs = self.jobs.lock()?;
+        Ok(jobs
+            .jobs
+            .values()
+            .filter(|job| job.username == username)
+            .map(|job| job.info.clone())
+            .collect())
+    }
+
+    /// Queued jobs are cancelled at once, running ones before their next probe. Jobs
+    /// that are done stay as they are.
+    pub fn cancel(&self, username: &str, id: usize) -> Result<JobInfo, ApiError> {
+        let mut jobs = self.jobs.lock()?;
+        let job = match jobs.jobs.get_mut(&id) {
+            Some(job) if job.username == username => job,
+            _ => return Err(job_not_found(id)),
+        };
+        if job.info.status.is_done() {
+            return Ok(job.info.clone());
+        }
+        job.cancelled = true;
+        info!("Cancelling learning job {}", id);
+        if job.info.status == JobStatus::Queued {
+            job.info.status = JobStatus::Cancelled;
+            job.route = None;
+            job.publish(JobEvent::Cancelled);
+            job.listeners.clear();
+            let info = job.info.clone();
+            jobs.queued.retain(|queued| *queued != id);
+            jobs.mark_finished(id);
+            return Ok(info);
+        }
+        Ok(job.info.clone())
+    }
+
+    /// Events of the job from now on, starting with its current state. The
+    /// stream ends when the job is done.
+    pub fn subscribe(
+        &self,
+        username: &str,
+        id: usize,
+    ) -> Result<UnboundedReceiver<JobEvent>, ApiError> {
+        let mut jobs = self.jobs.lock()?;
+        let job = match jobs.jobs.get_mut(&id) {
+            Some(job) if job.username == username => job,
+            _ => return Err(job_not_found(id)),
+        };
+        let (sender, receiver) = mpsc::unbounded();
+        let _ = sender.unbounded_send(JobEvent::Job {
+            job: job.info.clone(),
+        });
+        if !job.info.status.is_done() {
+            job.listeners.push(sender);
+        }
+        Ok(receiver)
+    }
+
+    /// Waits for the next queued job and marks it as running
+    fn next(&self) -> Result<(usize, String, Path, Option<u64>), ApiError> {
+        let mut jobs = self.jobs.lock()?;
+        loop {
+            if let Some(id) = jobs.queued.pop_front() {
+                if let Some(job) = jobs.jobs.get_mut(&id) {
+                    if let Some(route) = job.route.take() {
+                        job.info.status = JobStatus::Running;
+                        return Ok((id, job.username.clone(), route, job.request_id));
+                    }
+                }
+                continue;
+            }
+            jobs = self.available.wait(jobs)?;
+        }
+    }
+
+    fn is_cancelled(&self, id: usize) -> bool {
+        let jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        jobs.jobs.get(&id).map(|job| job.cancelled).unwrap_or(true)
+    }
+
+    fn update_progress(&self, id: usize, cut: usize) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(job) = jobs.jobs.get_mut(&id) {
+            job.info.progress.cut = cut;
+            job.info.progress.segments += 1;
+        }
+    }
+
+    fn publish(&self, id: usize, event: JobEvent) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(job) = jobs.jobs.get_mut(&id) {
+            job.publish(event);
+        }
+    }
+
+    /// Sends the last event of the job and ends the streams of its listeners
+    fn finish(&self, id: usize, event: JobEvent) {
+        let (status, error) = match &event {
+            JobEvent::Failed { error } => (JobStatus::Failed, Some(error.clone())),
+            JobEvent::Cancelled => (JobStatus::Cancelled, None),
+            _ => (JobStatus::Finished, None),
+        };
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(job) = jobs.jobs.get_mut(&id) {
+            job.info.status = status;
+            job.info.error = error;
+            job.publish(event);
+            job.listeners.clear();
+        }
+        jobs.mark_finished(id);
+    }
+}
+
+impl Job {
+    // listeners that went away are dropped
+    fn publish(&mut self, event: JobEvent) {
+        self.listeners
+            .retain(|listener| listener.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+impl Jobs {
+    fn mark_finished(&mut self, id: usize) {
+        self.finished.push_back(id);
+        while self.finished.len() > KEPT_FINISHED_JOBS {
+            if let Some(oldest) = self.finished.pop_front() {
+                self.jobs.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Reports the progress of a running job to the queue
+struct JobObserver<'a> {
+    queue: &'a JobQueue,
+    id: usize,
+}
+
+impl<'a> LearningObserver for JobObserver<'a> {
+    fn probe_started(&mut self, start: usize, end: usize) {
+        self.queue
+            .publish(self.id, JobEvent::ProbeStarted { start, end });
+    }
+
+    fn lp_solved(&mut self, alpha: Option<&Preference>) {
+        let alpha = alpha.copied();
+        self.queue.publish(self.id, JobEvent::LpSolved { alpha });
+    }
+
+    fn probe_finished(&mut self, start: usize, end: usize, alpha: Option<&Preference>) {
+        let alpha = alpha.copied();
+        self.queue
+            .publish(self.id, JobEvent::ProbeFinished { start, end, alpha });
+    }
+
+    fn segment_found(&mut self, start: usize, cut: usize, alpha: &Preference) {
+        self.queue.update_progress(self.id, cut);
+        self.queue.publish(
+            self.id,
+            JobEvent::SegmentFound {
+                start,
+                end: cut,
+                alpha: *alpha,
+            },
+        );
+    }
+
+    fn cancelled(&self) -> bool {
+        self.queue.is_cancelled(self.id)
+    }
+}
+
+/// Starts the threads that run the jobs of the queue of the state
+pub fn start_workers(state: web::Data<AppState>, workers: usize) {
+    for _ in 0..workers {
+        let state = state.clone();
+        thread::spawn(move || loop {
+            match state.jobs.next() {
+                Ok((id, username, route, request_id)) => {
+                    let _request = request_id.map(logging::enter_request);
+                    // a panicking job must neither stay running nor stop the worker
+                    let job = panic::AssertUnwindSafe(|| run_job(&state, id, &username, route));
+                    if panic::catch_unwind(job).is_err() {
+                        error!("Learning job {} panicked", id);
+                        let error = ApiError::new(ErrorCode::Internal, "Internal server error");
+                        state.jobs.finish(id, JobEvent::Failed { error });
+                    }
+                }
+                Err(_) => {
+                    warn!("Stopping learning worker, the job queue is poisoned");
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Learns the preferences of the route and stores them in the route of the
+/// user, if the user still has it unchanged
+fn run_job(state: &AppState, id: usize, username: &str, mut route: Path) {
+    info!("Running learning job {} for route {}", id, route.id);
+    let mut observer = JobObserver {
+        queue: &state.jobs,
+        id,
+    };
+    let result = learn_route(state, &mut route, &mut observer);
+    if state.jobs.is_cancelled(id) {
+        state.jobs.finish(id, JobEvent::Cancelled);
+        return;
+    }
+    let algo_split = route.algo_split.clone();
+    match result.and_then(|_| attach_result(state, username, route)) {
+        Ok(()) => {
+            info!("Finished learning job {}", id);
+            state.write_to_file();
+            state.jobs.finish(id, JobEvent::Finished { algo_split });
+        }
+        Err(error) => {
+            info!("Learning job {} failed: {}", id, error);
+            state.jobs.finish(id, JobEvent::Failed { error });
+        }
+    }
+}
+
+fn attach_result(state: &AppState, username: &str, route: Path) -> Result<(), ApiError> {
+    let mut users = state.users.lock()?;
+    let stored = users
+        .iter_mut()
+        .find(|user| user.auth.username == username)
+        .and_then(|user| user.route_mut(route.id))
+        .ok_or_else(|| ApiError::route_not_found(route.id))?;
+    if stored.edges != route.edges || stored.graph != route.graph {
+        return Err(ApiError::new(
+            ErrorCode::RouteChanged,
+            format!("Route {} was changed while it was learned", route.id),
+        ));
+    }
+    stored.algo_split = route.algo_split;
+    Ok(())
+}
+
+fn job_not_found(id: usize) -> ApiError {
+    ApiError::new(
+        ErrorCode::JobNotFound,
+        format!("There is no job with id {}", id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::load_graph;
+    use crate::server::tests::Database;
+    use futures::Stream;
+
+    fn state(database: &Database) -> AppState {
+        let graph = load_graph("default", "./src/test_graphs/testGraph").unwrap();
+        AppState::new(vec![graph], &database.0)
+    }
+
+    // a route without edges, its preference is found without the LP
+    fn single_node_route(state: &AppState) -> Path {
+        let graph = state.graphs()[0].clone();
+        let mut route = graph
+            .find_shortest_path(0, vec![0, 0], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        state.users.lock().unwrap()[0].add_route(&mut route);
+        route
+    }
+
+    #[test]
+    fn queue() {
+        let database = Database::new("jobs");
+        let state = state(&database);
+        let route = single_node_route(&state);
+        let first = state.jobs.submit("test", route.clone()).unwrap();
+        let second = state.jobs.submit("test", route.clone()).unwrap();
+        assert_eq!(JobStatus::Queued, first.status);
+        assert_eq!(1, first.progress.nodes);
+        assert_eq!(2, state.jobs.jobs("test").unwrap().len());
+        assert!(state.jobs.jobs("other").unwrap().is_empty());
+        assert_eq!(
+            ErrorCode::JobNotFound,
+            state.jobs.job("other", first.id).unwrap_err().code
+        );
+
+        let cancelled = state.jobs.cancel("test", second.id).unwrap();
+        assert_eq!(JobStatus::Cancelled, cancelled.status);
+
+        let (id, username, route, _) = state.jobs.next().unwrap();
+        assert_eq!(first.id, id);
+        assert_eq!(
+            JobStatus::Running,
+            state.jobs.job("test", id).unwrap().status
+        );
+        run_job(&state, id, &username, route);
+        assert_eq!(
+            JobStatus::Finished,
+            state.jobs.job("test", id).unwrap().status
+        );
+        let stored = &state.users.lock().unwrap()[0].driven_routes[0];
+        assert!(stored.algo_split.is_some());
+        // the finished job stored the users
+        assert!(std::fs::metadata(&database.0).is_ok());
+    }
+
+    #[test]
+    fn events() {
+        let database = Database::new("jobs-events");
+        let state = state(&database);
+        let route = single_node_route(&state);
+        let job = state.jobs.submit("test", route).unwrap();
+        let events = state.jobs.subscribe("test", job.id).unwrap();
+        assert!(state.jobs.subscribe("other", job.id).is_err());
+
+        let (id, username, route, _) = state.jobs.next().unwrap();
+        run_job(&state, id, &username, route);
+        let events: Vec<JobEvent> = events.wait().map(Result::unwrap).collect();
+        assert_eq!(2, events.len());
+        assert_eq!(JobEvent::Job { job }, events[0]);
+        match &events[1] {
+            JobEvent::Finished { algo_split } => assert!(algo_split.is_some()),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        assert_eq!(
+            r#"{"type":"lp_solved","alpha":null}"#,
+            serde_json::to_string(&JobEvent::LpSolved { alpha: None }).unwrap()
+        );
+
+        // done jobs only send their state
+        let events = state.jobs.subscribe("test", id).unwrap();
+        assert_eq!(1, events.wait().count());
+    }
+
+    #[test]
+    fn panicking_job() {
+        let database = Database::new("jobs-panic");
+        let mut state = state(&database);
+        let route = single_node_route(&state);
+        // the users can not be written into a missing directory, which panics
+        state.database_path = format!("{}/users", database.0);
+        let state = web::Data::new(state);
+        start_workers(state.clone(), 1);
+        let jobs = [
+            state.jobs.submit("test", route.clone()).unwrap(),
+            state.jobs.submit("test", route).unwrap(),
+        ];
+        // the second job runs on the worker that ran the first one
+        for job in jobs.iter() {
+            let mut info = state.jobs.job("test", job.id).unwrap();
+            for _ in 0..500 {
+                if info.status.is_done() {
+                    break;
+                }
+                thread::sleep(std::time::Duration::from_millis(10));
+                info = state.jobs.job("test", job.id).unwrap();
+            }
+            assert_eq!(JobStatus::Failed, info.status);
+            assert_eq!(ErrorCode::Internal, info.error.unwrap().code);
+        }
+    }
+
+    #[test]
+    fn deleted_route() {
+        let database = Database::new("jobs-deleted");
+        let state = state(&database);
+        let route = single_node_route(&state);
+        let job = state.jobs.submit("test", route.clone()).unwrap();
+        state.users.lock().unwrap()[0].delete_route(route.id);
+
+        let (id, username, route, _) = state.jobs.next().unwrap();
+        run_job(&state, id, &username, route);
+        let job = state.jobs.job("test", job.id).unwrap();
+        assert_eq!(JobStatus::Failed, job.status);
+        assert_eq!(ErrorCode::RouteNotFound, job.error.unwrap().code);
+    }
+}
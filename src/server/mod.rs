@@ -1,31 +1,63 @@
 use std::fs::File;
 use std::io::{Read, Write};
-use std::sync::Mutex;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 use actix_cors::Cors;
-use actix_web::dev::Service;
+use actix_service::NewService;
+use actix_web::dev::{Body, Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
 use actix_web::{web, App, HttpServer};
 use futures::Future;
+use log::{error, info, warn};
 
 use crate::config;
-use crate::graph::Graph;
+use crate::graph::customize::EdgeUpdate;
+use crate::graph::path::index_locations;
+use crate::graph::{load_graph, Graph};
+use crate::helpers::Coordinate;
+use crate::logging::{self, WithRequestId};
+use crate::metrics::metrics;
 use crate::user::UserState;
+use error::{ApiError, ErrorCode};
+use jobs::JobQueue;
 
 // use actix_web::dev::{Service, ServiceResponse};
 // use futures::{Future, IntoFuture};
 
+mod api;
 mod auth;
+mod error;
+mod jobs;
+mod openapi;
 mod routing;
+mod v2;
+mod validate;
+mod websocket;
+
+// Queries keep the snapshot of the graph they started with while updates and
+// reloads swap in a new one
+struct GraphSlot {
+    graph: RwLock<Arc<Graph>>,
+    reloading: AtomicBool,
+}
 
 pub struct AppState {
-    graph: Graph,
+    // one graph per region
+    graphs: Vec<GraphSlot>,
+    updating: Mutex<()>,
     database_path: String,
     users: Mutex<Vec<UserState>>,
+    jobs: JobQueue,
+    // token of administrative requests, which are rejected without one
+    admin_token: Option<String>,
 }
 
 impl AppState {
-    fn new(graph: Graph, database_path: &str) -> Self {
-        println!("Reading user database...");
+    fn new(graphs: Vec<Graph>, database_path: &str) -> Self {
+        info!("Reading user database {}", database_path);
         let users = match File::open(database_path) {
             Ok(mut file) => {
                 let mut content = String::new();
@@ -36,7 +68,7 @@ impl AppState {
                 users
             }
             Err(_) => {
-                println!("No database file existing");
+                info!("No database file existing, starting with the test user");
                 vec![UserState::new(
                     // test user
                     String::from("test"),
@@ -44,11 +76,180 @@ impl AppState {
                 )]
             }
         };
-        AppState {
-            graph,
+        let state = AppState {
+            graphs: graphs
+                .into_iter()
+                .map(|graph| GraphSlot {
+                    graph: RwLock::new(Arc::new(graph)),
+                    reloading: AtomicBool::new(false),
+                })
+                .collect(),
+            updating: Mutex::new(()),
             database_path: String::from(database_path),
             users: Mutex::new(users),
+            jobs: JobQueue::new(),
+            admin_token: None,
+        };
+        for graph in state.graphs() {
+            let unmatched = state.rematch_routes(&graph);
+            if !unmatched.is_empty() {
+                warn!(
+                    "{} stored routes on graph {} could not be matched and are stale",
+                    unmatched.len(),
+                    graph.name()
+                );
+                for (username, id) in unmatched {
+                    warn!("Route {} of user {} is stale", id, username);
+                }
+            }
+        }
+        // routes on graphs that are not served have no nodes and edges until
+        // their graph is loaded again
+        let names: Vec<String> = state
+            .graphs()
+            .iter()
+            .map(|graph| String::from(graph.name()))
+            .collect();
+        for user in state.users.lock().unwrap().iter_mut() {
+            for route in user
+                .driven_routes
+                .iter_mut()
+                .filter(|route| !names.contains(&route.graph))
+            {
+                warn!(
+                    "Route {} of user {} is on graph {}, which is not loaded, and is stale",
+                    route.id, user.auth.username, route.graph
+                );
+                route.stale = true;
+            }
+        }
+        state
+    }
+
+    fn graphs(&self) -> Vec<Arc<Graph>> {
+        self.graphs
+            .iter()
+            .map(|slot| slot.graph.read().unwrap().clone())
+            .collect()
+    }
+
+    /// Selects the graph with the given name, or else the first one whose
+    /// bounding box contains all waypoints. A single graph is used for everything.
+    fn graph(&self, name: Option<&str>, waypoints: &[Coordinate]) -> Option<Arc<Graph>> {
+        let mut graphs = self.graphs();
+        match name {
+            Some(name) => graphs.into_iter().find(|graph| graph.name() == name),
+            None if graphs.len() == 1 => graphs.pop(),
+            None if waypoints.is_empty() => None,
+            None => graphs.into_iter().find(|graph| {
+                waypoints
+                    .iter()
+                    .all(|waypoint| graph.bounding_box().contains(waypoint))
+            }),
+        }
+    }
+
+    /// Applies the updates to a copy of the graph and publishes it afterwards, so
+    /// that running queries are not blocked. Updates are applied one at a time.
+    fn update_graph(&self, name: Option<&str>, updates: &[EdgeUpdate]) -> Result<usize, ApiError> {
+        let _updating = self.updating.lock()?;
+        let index = match name {
+            Some(name) => self
+                .graphs
+                .iter()
+                .position(|slot| slot.graph.read().unwrap().name() == name),
+            None if self.graphs.len() == 1 => Some(0),
+            None => None,
+        }
+        .ok_or_else(ApiError::unknown_graph)?;
+        let mut graph = Graph::clone(&*self.graphs[index].graph.read()?);
+        let num_of_shortcuts = graph
+            .update_edge_costs(updates)
+            .map_err(|message| ApiError::new(ErrorCode::InvalidEdgeUpdate, message))?;
+        *self.graphs[index].graph.write()? = Arc::new(graph);
+        Ok(num_of_shortcuts)
+    }
+
+    /// Marks the graph with the given name as reloading. Returns its index, or
+    /// an error if the name is unknown or a reload is already running.
+    fn start_reload(&self, name: &str) -> Result<usize, ApiError> {
+        let index = self
+            .graphs
+            .iter()
+            .position(|slot| slot.graph.read().unwrap().name() == name)
+            .ok_or_else(ApiError::unknown_graph)?;
+        if self.graphs[index].reloading.swap(true, Ordering::SeqCst) {
+            return Err(ApiError::new(
+                ErrorCode::ReloadInProgress,
+                format!("Graph {} is already being reloaded", name),
+            ));
+        }
+        Ok(index)
+    }
+
+    /// Loads the graph again from its file and swaps it in. Stored routes on the
+    /// graph are matched to the new one by their coordinates and marked as stale
+    /// if that fails.
+    fn reload_graph(&self, index: usize) {
+        let old_graph = self.graphs[index].graph.read().unwrap().clone();
+        let name = old_graph.name();
+        let file_path = old_graph.file_path();
+        info!("Reloading graph {} from {}", name, file_path);
+
+        // the parser panics on some malformed files, which must not keep the flag set
+        let loaded =
+            panic::catch_unwind(|| load_graph(name, file_path).map_err(|err| err.to_string()));
+        match loaded {
+            Ok(Ok(graph)) => {
+                let graph = Arc::new(graph);
+                {
+                    let _updating = self.updating.lock().unwrap();
+                    *self.graphs[index].graph.write().unwrap() = graph.clone();
+                }
+                let num_of_stale = self.rematch_routes(&graph).len();
+                self.write_to_file();
+                info!(
+                    "Reloaded graph {}, {} stored routes are stale",
+                    name, num_of_stale
+                );
+            }
+            Ok(Err(message)) => error!("Could not reload graph {}: {}", name, message),
+            Err(_) => error!("Could not reload graph {}: malformed graph file", name),
+        }
+        self.graphs[index].reloading.store(false, Ordering::SeqCst);
+    }
+
+    /// Matches the stored routes on the graph to its nodes and edges by their
+    /// coordinates. Routes that do not match are marked as stale and returned as
+    /// (username, route id).
+    fn rematch_routes(&self, graph: &Graph) -> Vec<(String, usize)> {
+        let locations = index_locations(graph);
+        let mut unmatched = Vec::new();
+        let mut users = self.users.lock().unwrap();
+        for user in users.iter_mut() {
+            for route in user
+                .driven_routes
+                .iter_mut()
+                .filter(|route| route.graph == graph.name())
+            {
+                if route.matches(graph) {
+                    continue;
+                }
+                if !route.fingerprint.is_empty() && route.fingerprint != graph.fingerprint() {
+                    info!(
+                        "Route {} of user {} was stored on another version of graph {}",
+                        route.id,
+                        user.auth.username,
+                        graph.name()
+                    );
+                }
+                if !route.rematch(graph, &locations) {
+                    route.stale = true;
+                    unmatched.push((user.auth.username.clone(), route.id));
+                }
+            }
         }
+        unmatched
     }
 
     fn write_to_file(&self) {
@@ -59,67 +260,690 @@ impl AppState {
     }
 }
 
-pub fn start_server(graph: Graph) {
-    let config = config::get_config();
-    let state = web::Data::new(AppState::new(graph, config.database_path()));
-    println!("Starting server");
-    HttpServer::new(move || {
-        App::new()
-            .register_data(state.clone())
-            .wrap(Cors::new().allowed_origin("http://localhost:8080"))
-            .wrap_fn(|req, srv| {
-                srv.call(req).map(|res| {
-                    let req = res.request();
-                    let state: &AppState = req.app_data().unwrap();
-                    state.write_to_file();
-                    res
-                })
+/// All endpoints with the middleware of the server, which stores the users
+/// after every request
+fn app(
+    state: web::Data<AppState>,
+) -> App<
+    impl NewService<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+    Body,
+> {
+    App::new()
+        .register_data(state)
+        .configure(api::configure)
+        .wrap(Cors::new().allowed_origin("http://localhost:8080"))
+        .wrap_fn(|req, srv| {
+            srv.call(req).map(|res| {
+                let req = res.request();
+                let state: &AppState = req.app_data().unwrap();
+                state.write_to_file();
+                res
             })
-            /*
-            .wrap_fn(|req, srv| {
-                let unauth: Box<dyn IntoFuture<Item = ServiceResponse>> =
-                    Box::new(ServiceResponse::new(
-                        req.into_parts().0,
-                        HttpResponse::Unauthorized().finish(),
-                    ));
-                let auth_header = req.headers().get("Authorization");
-                match auth_header {
-                    None => unauth,
-                    Some(value) => {
-                        let token = value.to_str().unwrap();
-                        let mut users = state.users.lock().unwrap();
-                        let user_state = users.iter_mut().find(|x| x.auth.token == token);
-                        match user_state {
-                            None => unauth,
-                            Some(user) => Box::new(srv.call(req).map(|res| res)),
-                        }
+        })
+        .wrap_fn(|req, srv| {
+            let id = logging::next_request_id();
+            let started = Instant::now();
+            let _request = logging::enter_request(id);
+            info!("{} {}", req.method(), req.path());
+            WithRequestId::new(
+                id,
+                srv.call(req).map(move |mut res| {
+                    let metrics = metrics();
+                    metrics
+                        .count_http_request(res.request().method().as_str(), res.status().as_u16());
+                    metrics
+                        .http_seconds
+                        .observe(started.elapsed().as_secs_f64());
+                    info!(
+                        "{} {} {} in {} ms",
+                        res.request().method(),
+                        res.request().path(),
+                        res.status().as_u16(),
+                        started.elapsed().as_millis()
+                    );
+                    if let Ok(value) = HeaderValue::from_str(&logging::format_request_id(id)) {
+                        res.headers_mut()
+                            .insert(HeaderName::from_static("x-request-id"), value);
                     }
+                    res
+                }),
+            )
+        })
+    /*
+    .wrap_fn(|req, srv| {
+        let unauth: Box<dyn IntoFuture<Item = ServiceResponse>> =
+            Box::new(ServiceResponse::new(
+                req.into_parts().0,
+                HttpResponse::Unauthorized().finish(),
+            ));
+        let auth_header = req.headers().get("Authorization");
+        match auth_header {
+            None => unauth,
+            Some(value) => {
+                let token = value.to_str().unwrap();
+                let mut users = state.users.lock().unwrap();
+                let user_state = users.iter_mut().find(|x| x.auth.token == token);
+                match user_state {
+                    None => unauth,
+                    Some(user) => Box::new(srv.call(req).map(|res| res)),
                 }
-            })
-            */
-            // routing stuff
-            .route("/tags", web::get().to(routing::get_cost_tags))
-            .route("/preference", web::get().to(routing::get_preference))
-            .route("/preference", web::post().to(routing::set_preference))
-            .route("/preference/new", web::post().to(routing::new_preference))
-            .route("/preference/find", web::post().to(routing::find_preference))
-            .route("/closest", web::get().to(routing::find_closest))
-            .route("/fsp", web::post().to(routing::fsp))
-            .route("/routes", web::get().to(routing::get_routes))
-            .route("/delete/{id}", web::post().to(routing::delete_route))
-            .route("/reset", web::post().to(routing::reset_data))
-
-            // auth stuff
-            .route("/login", web::post().to(auth::login))
-            .route("/register", web::post().to(auth::register))
+            }
+        }
     })
-    .bind(format!("0.0.0.0:{}", config.port()))
-    .expect("Can not bind to port 8000")
-    .run()
-    .expect("Could not start sever");
+    */
+}
+
+pub fn start_server(graphs: Vec<Graph>) {
+    let config = config::get_config();
+    let mut state = AppState::new(graphs, config.database_path());
+    state.admin_token = config.admin_token().map(String::from);
+    let state = web::Data::new(state);
+    jobs::start_workers(state.clone(), config.learning_workers());
+    info!("Starting server on port {}", config.port());
+    HttpServer::new(move || app(state.clone()))
+        .bind(format!("0.0.0.0:{}", config.port()))
+        .expect("Can not bind to port 8000")
+        .run()
+        .expect("Could not start sever");
 }
 
 #[cfg(test)]
 mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use actix_http::Request;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use futures::Stream;
+    use serde_json::{json, Value};
+
     use super::*;
+    use crate::graph::generator::generate_grid_graph;
+    use crate::graph::path::Path;
+    use jobs::{JobEvent, JobInfo, JobStatus};
+
+    // user database in the temporary directory, removed when dropped
+    pub(super) struct Database(pub(super) String);
+
+    impl Database {
+        pub(super) fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("preference-routing-{}-test", name));
+            let _ = std::fs::remove_file(&path);
+            Database(String::from(path.to_str().unwrap()))
+        }
+    }
+
+    impl Drop for Database {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const ADMIN_TOKEN: &str = "admin";
+
+    // the server on a 4x4 grid, a new one reads the users the last one stored
+    fn start(
+        database: &Database,
+    ) -> (
+        web::Data<AppState>,
+        impl Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    ) {
+        let mut state = AppState::new(vec![generate_grid_graph(4, 4)], &database.0);
+        state.admin_token = Some(String::from(ADMIN_TOKEN));
+        let state = web::Data::new(state);
+        (state.clone(), test::init_service(app(state)))
+    }
+
+    // status and JSON body of the answer, the body is null if it is empty
+    fn call<S>(
+        app: &mut S,
+        method: Method,
+        uri: &str,
+        token: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value)
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let mut request = TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", token);
+        if let Some(body) = body {
+            request = request.set_json(&body);
+        }
+        let response = test::call_service(app, request.to_request());
+        let status = response.status();
+        let body = test::read_body(response);
+        if body.is_empty() {
+            return (status, Value::Null);
+        }
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn login<S>(app: &mut S, username: &str, password: &str) -> String
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let credentials = json!({ "username": username, "password": password });
+        let (status, token) = call(app, Method::POST, "/login", "", Some(credentials));
+        assert_eq!(StatusCode::OK, status);
+        String::from(token.as_str().unwrap())
+    }
+
+    // from node 0 at the origin to node 15 in the opposite corner of the grid
+    fn route_request() -> Value {
+        json!({
+            "waypoints": [{ "lat": 0.0, "lng": 0.0 }, { "lat": 3.0, "lng": 3.0 }],
+            "alpha": [1.0, 0.0, 0.0, 0.0],
+        })
+    }
+
+    fn create_route<S>(app: &mut S, token: &str) -> Path
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let (status, route) = call(
+            app,
+            Method::POST,
+            "/v2/routes",
+            token,
+            Some(route_request()),
+        );
+        assert_eq!(StatusCode::CREATED, status);
+        serde_json::from_value(route).unwrap()
+    }
+
+    fn route_ids(routes: Value) -> Vec<usize> {
+        let routes: Vec<Path> = serde_json::from_value(routes).unwrap();
+        routes.iter().map(|route| route.id).collect()
+    }
+
+    #[test]
+    fn graph_selection() {
+        let mut small = generate_grid_graph(3, 3);
+        small.set_name("small");
+        let mut large = generate_grid_graph(6, 6);
+        large.set_name("large");
+        let database = Database::new("graph-selection");
+        let state = AppState::new(vec![small, large], &database.0);
+
+        let inside_small = Coordinate { lat: 1.0, lng: 2.0 };
+        let inside_large = Coordinate { lat: 4.0, lng: 2.0 };
+        let outside = Coordinate { lat: 9.0, lng: 2.0 };
+        let name = |graph: Option<Arc<Graph>>| graph.map(|graph| String::from(graph.name()));
+        assert_eq!(
+            Some("small".into()),
+            name(state.graph(None, &[inside_small]))
+        );
+        assert_eq!(
+            Some("large".into()),
+            name(state.graph(None, &[inside_small, inside_large]))
+        );
+        assert_eq!(None, name(state.graph(None, &[outside])));
+        assert_eq!(
+            Some("large".into()),
+            name(state.graph(Some("large"), &[inside_small]))
+        );
+        assert_eq!(None, name(state.graph(Some("unknown"), &[inside_small])));
+        assert!(state.update_graph(None, &[]).is_err());
+        assert!(state.update_graph(Some("small"), &[]).is_ok());
+    }
+
+    #[test]
+    fn graph_reload() {
+        let database = Database::new("reload");
+        let graph_path = std::env::temp_dir().join("preference-routing-reload-graph-test");
+        let graph_path = graph_path.to_str().unwrap();
+        std::fs::copy("./src/test_graphs/testGraph", graph_path).unwrap();
+        let graph = load_graph("default", graph_path).unwrap();
+        let route = graph
+            .find_shortest_path(0, vec![2, 10], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        let state = AppState::new(vec![graph], &database.0);
+        state.users.lock().unwrap()[0].add_route(&mut route.clone());
+
+        let index = state.start_reload("default").unwrap();
+        assert_eq!(
+            ErrorCode::ReloadInProgress,
+            state.start_reload("default").unwrap_err().code
+        );
+        assert_eq!(
+            ErrorCode::UnknownGraph,
+            state.start_reload("unknown").unwrap_err().code
+        );
+        // the contracted graph keeps the original edges
+        std::fs::copy("./src/test_graphs/concTestGraph", graph_path).unwrap();
+        state.reload_graph(index);
+        assert_eq!(26, state.graphs()[0].edges.len());
+        assert!(!state.users.lock().unwrap()[0].driven_routes[0].stale);
+
+        // the old graph stays if the file can not be loaded
+        std::fs::remove_file(graph_path).unwrap();
+        let index = state.start_reload("default").unwrap();
+        state.reload_graph(index);
+        assert_eq!(26, state.graphs()[0].edges.len());
+        assert!(state.start_reload("default").is_ok());
+    }
+
+    // graph file of a square from (0, 0) to (1, 1), the side through (1, 0) is
+    // cheaper and its node has the given id, the other one through (0, 1)
+    fn square_graph(cheap: usize) -> String {
+        let expensive = 3 - cheap;
+        let mut locations = [(0.0, 0.0); 4];
+        locations[cheap] = (1.0, 0.0);
+        locations[expensive] = (0.0, 1.0);
+        locations[3] = (1.0, 1.0);
+        let mut lines = vec![String::from("#\n#\n#\n\n4\n4\n8")];
+        for (id, (lat, lng)) in locations.iter().enumerate() {
+            lines.push(format!("{} 0 {} {} 0 0", id, lat, lng));
+        }
+        for (node, cost) in &[(cheap, 1), (expensive, 2)] {
+            for (source, target) in &[(0, *node), (*node, 0), (*node, 3), (3, *node)] {
+                lines.push(format!(
+                    "{} {} {c} {c} {c} {c} -1 -1",
+                    source,
+                    target,
+                    c = cost
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn avoid_after_reload() {
+        let database = Database::new("avoid-reload");
+        // removed when dropped like a database
+        let graph_file = Database::new("avoid-reload-graph");
+        std::fs::write(&graph_file.0, square_graph(1)).unwrap();
+        let graph = load_graph("default", &graph_file.0).unwrap();
+        let state = web::Data::new(AppState::new(vec![graph], &database.0));
+        let mut app = test::init_service(app(state.clone()));
+        let token = login(&mut app, "test", "testtest");
+        let request = json!({
+            "waypoints": [{ "lat": 0.0, "lng": 0.0 }, { "lat": 1.0, "lng": 1.0 }],
+            "alpha": [1.0, 0.0, 0.0, 0.0],
+            "avoid": { "nodes": [2] },
+        });
+        let (status, route) = call(&mut app, Method::POST, "/v2/routes", &token, Some(request));
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(json!({ "lat": 1.0, "lng": 0.0 }), route["coordinates"][1]);
+
+        // in the new graph, node 2 is the one the route takes
+        std::fs::write(&graph_file.0, square_graph(2)).unwrap();
+        let index = state.start_reload("default").unwrap();
+        state.reload_graph(index);
+        let uri = format!("/v2/routes/{}", route["id"]);
+        let patch = json!({ "alpha": [0.0, 1.0, 0.0, 0.0] });
+        let (status, route) = call(&mut app, Method::PATCH, &uri, &token, Some(patch));
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!([]), route["avoid"]["nodes"]);
+        assert_eq!(json!({ "lat": 1.0, "lng": 0.0 }), route["coordinates"][1]);
+    }
+
+    #[test]
+    fn stored_routes() {
+        let database = Database::new("stored");
+        let database_path = &database.0;
+        let graph = generate_grid_graph(4, 4);
+        let route = graph
+            .find_shortest_path(0, vec![0, 15], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        let state = AppState::new(vec![graph], database_path);
+        state.users.lock().unwrap()[0].add_route(&mut route.clone());
+        state.write_to_file();
+
+        // stored routes keep their coordinates only and are matched on load
+        let wider = generate_grid_graph(5, 4);
+        let state = AppState::new(vec![wider.clone()], database_path);
+        let stored = state.users.lock().unwrap()[0].driven_routes[0].clone();
+        assert!(!stored.stale);
+        assert!(stored.matches(&wider));
+        assert_eq!(route.coordinates, stored.coordinates);
+
+        let state = AppState::new(vec![generate_grid_graph(2, 2)], database_path);
+        assert!(state.users.lock().unwrap()[0].driven_routes[0].stale);
+
+        // the graph of the route is not served
+        let mut other = wider.clone();
+        other.set_name("other");
+        let state = AppState::new(vec![other], database_path);
+        assert!(state.users.lock().unwrap()[0].driven_routes[0].stale);
+        state.write_to_file();
+        let state = AppState::new(vec![wider], database_path);
+        assert!(!state.users.lock().unwrap()[0].driven_routes[0].stale);
+    }
+
+    #[test]
+    fn register_and_login() {
+        let database = Database::new("login");
+        let (_, mut app) = start(&database);
+        let credentials = json!({ "username": "alice", "password": "secret" });
+        let register = |app: &mut _| {
+            call(
+                app,
+                Method::POST,
+                "/register",
+                "",
+                Some(credentials.clone()),
+            )
+        };
+        assert_eq!((StatusCode::OK, Value::Null), register(&mut app));
+        let (status, error) = register(&mut app);
+        assert_eq!(StatusCode::CONFLICT, status);
+        assert_eq!(json!("USERNAME_TAKEN"), error["code"]);
+
+        let wrong = json!({ "username": "alice", "password": "wrong" });
+        let (status, error) = call(&mut app, Method::POST, "/login", "", Some(wrong));
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        assert_eq!(json!("UNAUTHORIZED"), error["code"]);
+
+        let token = login(&mut app, "alice", "secret");
+        assert_eq!(token, login(&mut app, "alice", "secret"));
+        assert_ne!(token, login(&mut app, "test", "testtest"));
+        let (status, routes) = call(&mut app, Method::GET, "/routes", &token, None);
+        assert_eq!((StatusCode::OK, json!([])), (status, routes));
+        for token in &["", "unknown"] {
+            let (status, error) = call(&mut app, Method::GET, "/routes", token, None);
+            assert_eq!(StatusCode::UNAUTHORIZED, status);
+            assert_eq!(json!("UNAUTHORIZED"), error["code"]);
+        }
+    }
+
+    #[test]
+    fn routing() {
+        let database = Database::new("routing");
+        let (state, mut app) = start(&database);
+        let token = login(&mut app, "test", "testtest");
+
+        let mut request = route_request();
+        request["id"] = json!(0);
+        let (status, route) = call(&mut app, Method::POST, "/fsp", &token, Some(request));
+        assert_eq!(StatusCode::OK, status);
+        let route: Path = serde_json::from_value(route).unwrap();
+        let expected = state.graphs()[0]
+            .find_shortest_path(0, vec![0, 15], [1.0, 0.0, 0.0, 0.0])
+            .unwrap();
+        assert!(!route.edges.is_empty());
+        assert_eq!(expected.nodes, route.nodes);
+        assert_eq!(expected.edges, route.edges);
+        assert_eq!("default", route.graph);
+        // routes with id 0 are not stored
+        let (_, routes) = call(&mut app, Method::GET, "/routes", &token, None);
+        assert!(route_ids(routes).is_empty());
+
+        let created = create_route(&mut app, &token);
+        assert_eq!(1, created.id);
+        let (status, route) = call(&mut app, Method::GET, "/v2/routes/1", &token, None);
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(
+            created.nodes,
+            serde_json::from_value::<Path>(route).unwrap().nodes
+        );
+        let (_, routes) = call(&mut app, Method::GET, "/routes", &token, None);
+        assert_eq!(vec![1], route_ids(routes));
+        assert_eq!(None, created.order);
+        let mut request = route_request();
+        request["optimize_order"] = json!(true);
+        let (status, route) = call(&mut app, Method::POST, "/v2/routes", &token, Some(request));
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(json!([0, 1]), route["order"]);
+
+        // the avoided parts are kept unless the patch changes them
+        let mut request = route_request();
+        request["avoid"] = json!({ "nodes": [5] });
+        let (_, route) = call(&mut app, Method::POST, "/v2/routes", &token, Some(request));
+        let uri = format!("/v2/routes/{}", route["id"]);
+        let patch = json!({ "alpha": [0.0, 1.0, 0.0, 0.0] });
+        let (status, route) = call(&mut app, Method::PATCH, &uri, &token, Some(patch));
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!([5]), route["avoid"]["nodes"]);
+        let patch = json!({ "avoid": {} });
+        let (_, route) = call(&mut app, Method::PATCH, &uri, &token, Some(patch));
+        assert_eq!(json!([]), route["avoid"]["nodes"]);
+
+        let mut request = route_request();
+        request["waypoints"] = json!([{ "lat": 0.0, "lng": 0.0 }]);
+        let (status, error) = call(&mut app, Method::POST, "/v2/routes", &token, Some(request));
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(json!("INVALID_WAYPOINTS"), error["code"]);
+        let mut request = route_request();
+        request["alpha"] = json!([0.0, 0.0, 0.0, 0.0]);
+        let (status, error) = call(&mut app, Method::POST, "/v2/routes", &token, Some(request));
+        assert_eq!(StatusCode::BAD_REQUEST, status);
+        assert_eq!(json!("INVALID_PREFERENCE"), error["code"]);
+    }
+
+    #[test]
+    fn learning() {
+        let database = Database::new("learning");
+        let (state, mut app) = start(&database);
+        let token = login(&mut app, "test", "testtest");
+        // the second costs of the edge from node 5 to node 6 make the route for
+        // alpha [1, 0, 0, 0] more expensive than another one for equal alphas,
+        // so learning it needs an LP
+        let edge = state.graphs()[0]
+            .edges
+            .iter()
+            .find(|edge| edge.source_id == 5 && edge.target_id == 6)
+            .unwrap()
+            .id;
+        let updates = json!([{ "edge": edge, "costs": [1.0, 9.0, 0.5, 2.0] }]);
+        let (status, _) = call(
+            &mut app,
+            Method::POST,
+            "/edges/costs",
+            ADMIN_TOKEN,
+            Some(updates),
+        );
+        assert_eq!(StatusCode::OK, status);
+
+        let mut request = route_request();
+        request["id"] = json!(0);
+        let (status, routes) = call(
+            &mut app,
+            Method::POST,
+            "/preference/find",
+            &token,
+            Some(request),
+        );
+        assert_eq!(StatusCode::OK, status);
+        let routes: Vec<Path> = serde_json::from_value(routes).unwrap();
+        assert_eq!(1, routes.len());
+        assert!(routes[0].algo_split.is_some());
+
+        let route = create_route(&mut app, &token);
+        assert!(route.algo_split.is_none());
+        let uri = format!("/v2/routes/{}/learn", route.id);
+        let (status, job) = call(&mut app, Method::POST, &uri, &token, None);
+        assert_eq!(StatusCode::ACCEPTED, status);
+        let job: JobInfo = serde_json::from_value(job).unwrap();
+        assert_eq!(route.id, job.route_id);
+        let events = state.jobs.subscribe("test", job.id).unwrap();
+        jobs::start_workers(state, 1);
+        let uri = format!("/v2/jobs/{}", job.id);
+        let mut job = job;
+        for _ in 0..500 {
+            if job.status != JobStatus::Queued && job.status != JobStatus::Running {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            let (_, info) = call(&mut app, Method::GET, &uri, &token, None);
+            job = serde_json::from_value(info).unwrap();
+        }
+        assert_eq!(JobStatus::Finished, job.status);
+        let uri = format!("/v2/routes/{}", route.id);
+        let (_, route) = call(&mut app, Method::GET, &uri, &token, None);
+        let route: Path = serde_json::from_value(route).unwrap();
+        assert!(route.algo_split.is_some());
+        let solved = events
+            .wait()
+            .map(Result::unwrap)
+            .filter(|event| matches!(event, JobEvent::LpSolved { .. }))
+            .count();
+        assert!(solved > 0);
+    }
+
+    #[test]
+    fn preference_regions() {
+        let database = Database::new("regions");
+        let (_, mut app) = start(&database);
+        let waypoints = json!([{ "lat": 0.0, "lng": 0.0 }, { "lat": 3.0, "lng": 3.0 }]);
+        let request = json!({ "waypoints": waypoints, "alpha": [1.0, 0.0, 0.0, 0.0] });
+        let (status, sensitivity) = call(
+            &mut app,
+            Method::POST,
+            "/preference/sensitivity",
+            "",
+            Some(request),
+        );
+        assert_eq!(StatusCode::OK, status);
+        assert!(sensitivity.is_object());
+
+        // the two parts of the graph are not connected
+        let graph = load_graph("default", "./src/test_graphs/disconnectedTestGraph").unwrap();
+        let state = web::Data::new(AppState::new(vec![graph], &database.0));
+        let mut app = test::init_service(super::app(state));
+        let request = json!({
+            "source": { "lat": 0.0, "lng": 0.0 },
+            "target": { "lat": 1.0, "lng": 1.0 },
+        });
+        let (status, error) = call(
+            &mut app,
+            Method::POST,
+            "/preference/partition",
+            "",
+            Some(request),
+        );
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!(json!("NO_ROUTE"), error["code"]);
+    }
+
+    #[test]
+    fn route_deletion() {
+        let database = Database::new("deletion");
+        let (_, mut app) = start(&database);
+        let token = login(&mut app, "test", "testtest");
+        for _ in 0..3 {
+            create_route(&mut app, &token);
+        }
+
+        let (status, routes) = call(&mut app, Method::POST, "/delete/1", &token, None);
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(vec![2, 3], route_ids(routes));
+        // deleting an unknown route keeps the others
+        let (status, routes) = call(&mut app, Method::POST, "/delete/1", &token, None);
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(vec![2, 3], route_ids(routes));
+
+        let (status, body) = call(&mut app, Method::DELETE, "/v2/routes/2", &token, None);
+        assert_eq!((StatusCode::NO_CONTENT, Value::Null), (status, body));
+        for method in [Method::DELETE, Method::GET].iter() {
+            let (status, error) = call(&mut app, method.clone(), "/v2/routes/2", &token, None);
+            assert_eq!(StatusCode::NOT_FOUND, status);
+            assert_eq!(json!("ROUTE_NOT_FOUND"), error["code"]);
+        }
+        let (_, routes) = call(&mut app, Method::GET, "/routes", &token, None);
+        assert_eq!(vec![3], route_ids(routes));
+        // ids are not reused
+        assert_eq!(4, create_route(&mut app, &token).id);
+    }
+
+    #[test]
+    fn admin_requests() {
+        let database = Database::new("admin");
+        let (state, mut app) = start(&database);
+        let token = login(&mut app, "test", "testtest");
+        let updates = json!([{ "edge": 0, "costs": [5.0, 5.0, 5.0, 5.0] }]);
+        for token in [token.as_str(), ""].iter() {
+            let (status, error) = call(
+                &mut app,
+                Method::POST,
+                "/edges/costs",
+                token,
+                Some(updates.clone()),
+            );
+            assert_eq!(StatusCode::UNAUTHORIZED, status);
+            assert_eq!(json!("UNAUTHORIZED"), error["code"]);
+        }
+        let (status, _) = call(
+            &mut app,
+            Method::POST,
+            "/graphs/default/reload",
+            &token,
+            None,
+        );
+        assert_eq!(StatusCode::UNAUTHORIZED, status);
+        assert_ne!([5.0, 5.0, 5.0, 5.0], state.graphs()[0].edges[0].edge_costs);
+
+        let (status, body) = call(
+            &mut app,
+            Method::POST,
+            "/edges/costs",
+            ADMIN_TOKEN,
+            Some(updates),
+        );
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!(json!(1), body["edges"]);
+        assert_eq!([5.0, 5.0, 5.0, 5.0], state.graphs()[0].edges[0].edge_costs);
+    }
+
+    #[test]
+    fn reset() {
+        let database = Database::new("reset");
+        let (_, mut app) = start(&database);
+        let credentials = json!({ "username": "alice", "password": "secret" });
+        call(&mut app, Method::POST, "/register", "", Some(credentials));
+        let other = login(&mut app, "alice", "secret");
+        create_route(&mut app, &other);
+        let token = login(&mut app, "test", "testtest");
+        create_route(&mut app, &token);
+        let (_, alphas) = call(&mut app, Method::POST, "/preference/new", &token, None);
+        assert_eq!(2, alphas.as_array().unwrap().len());
+
+        let (status, body) = call(&mut app, Method::POST, "/reset", &token, None);
+        assert_eq!((StatusCode::OK, Value::Null), (status, body));
+        let (_, routes) = call(&mut app, Method::GET, "/routes", &token, None);
+        assert!(route_ids(routes).is_empty());
+        let (_, alphas) = call(&mut app, Method::GET, "/preference", &token, None);
+        assert_eq!(json!([config::get_config().initial_pref()]), alphas);
+        // other users keep their data
+        let (_, routes) = call(&mut app, Method::GET, "/routes", &other, None);
+        assert_eq!(vec![1], route_ids(routes));
+    }
+
+    #[test]
+    fn persistence() {
+        let database = Database::new("persistence");
+        let (_, mut app) = start(&database);
+        let credentials = json!({ "username": "alice", "password": "secret" });
+        call(&mut app, Method::POST, "/register", "", Some(credentials));
+        let token = login(&mut app, "alice", "secret");
+        let route = create_route(&mut app, &token);
+        call(&mut app, Method::POST, "/preference/new", &token, None);
+        drop(app);
+
+        // the restarted server knows the user, the route and the preferences
+        let (_, mut app) = start(&database);
+        assert_eq!(token, login(&mut app, "alice", "secret"));
+        let (_, routes) = call(&mut app, Method::GET, "/v2/routes", &token, None);
+        let routes: Vec<Path> = serde_json::from_value(routes).unwrap();
+        assert_eq!(1, routes.len());
+        assert_eq!(route.id, routes[0].id);
+        // routes are stored by their coordinates and found again in the graph
+        assert_eq!(route.coordinates, routes[0].coordinates);
+        assert_eq!(route.nodes, routes[0].nodes);
+        assert!(!routes[0].stale);
+        let (_, alphas) = call(&mut app, Method::GET, "/preference", &token, None);
+        assert_eq!(2, alphas.as_array().unwrap().len());
+        assert_eq!(2, create_route(&mut app, &token).id);
+    }
 }
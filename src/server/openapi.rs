@@ -0,0 +1,578 @@
+use std::any::type_name;
+use std::collections::HashSet;
+use std::fmt;
+
+use actix_web::http::{Method, StatusCode};
+use actix_web::{HttpResponse, Route};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::forward_to_deserialize_any;
+use serde_json::{json, Map, Value};
+
+use super::api::endpoints;
+use super::error::ApiError;
+
+/// Operation of the API. The route handles the requests, the rest describes
+/// them in the OpenAPI document.
+pub struct Endpoint {
+    pub id: &'static str,
+    pub method: Method,
+    pub path: &'static str,
+    pub route: fn(Route) -> Route,
+    summary: &'static str,
+    // name of the security scheme the request needs
+    security: Option<&'static str>,
+    parameters: Vec<Value>,
+    body: Option<Value>,
+    responses: Vec<(StatusCode, Option<(&'static str, Value)>)>,
+}
+
+impl Endpoint {
+    pub fn new(
+        id: &'static str,
+        method: Method,
+        path: &'static str,
+        route: fn(Route) -> Route,
+    ) -> Self {
+        Endpoint {
+            id,
+            method,
+            path,
+            route,
+            summary: "",
+            security: None,
+            parameters: Vec::new(),
+            body: None,
+            responses: Vec::new(),
+        }
+    }
+
+    pub fn summary(mut self, summary: &'static str) -> Self {
+        self.summary = summary;
+        self
+    }
+
+    /// The request needs the token of a user in the Authorization header
+    pub fn authorized(mut self) -> Self {
+        self.security = Some("token");
+        self
+    }
+
+    /// The request needs the admin token in the Authorization header
+    pub fn admin(mut self) -> Self {
+        self.security = Some("admin");
+        self
+    }
+
+    /// The part {name} of the path is a T
+    pub fn param<T: DeserializeOwned>(mut self, name: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": request_schema::<T>(),
+        }));
+        self
+    }
+
+    /// The query string is a T
+    pub fn query<T: DeserializeOwned>(mut self) -> Self {
+        let schema = request_schema::<T>();
+        let required = schema["required"].as_array().cloned().unwrap_or_default();
+        if let Some(properties) = schema["properties"].as_object() {
+            for (name, property) in properties {
+                self.parameters.push(json!({
+                    "name": name,
+                    "in": "query",
+                    "required": required.contains(&json!(name)),
+                    "schema": property,
+                }));
+            }
+        }
+        self
+    }
+
+    /// The body is a T as JSON
+    pub fn body<T: DeserializeOwned>(mut self) -> Self {
+        self.body = Some(request_schema::<T>());
+        self
+    }
+
+    /// Answers with the status and a T as JSON
+    pub fn response<T: DeserializeOwned>(self, status: StatusCode) -> Self {
+        self.content(status, "application/json", response_schema::<T>())
+    }
+
+    pub fn content(
+        mut self,
+        status: StatusCode,
+        content_type: &'static str,
+        schema: Value,
+    ) -> Self {
+        self.responses.push((status, Some((content_type, schema))));
+        self
+    }
+
+    /// Answers with the status and no body
+    pub fn empty(mut self, status: StatusCode) -> Self {
+        self.responses.push((status, None));
+        self
+    }
+
+    fn operation(&self) -> Value {
+        let mut responses: Map<String, Value> = self
+            .responses
+            .iter()
+            .map(|(status, content)| {
+                let mut response = json!({
+                    "description": status.canonical_reason().unwrap_or_default(),
+                });
+                if let Some((content_type, schema)) = content {
+                    response["content"] = json!({ *content_type: { "schema": schema } });
+                }
+                (status.as_u16().to_string(), response)
+            })
+            .collect();
+        responses.insert(
+            String::from("default"),
+            json!({
+                "description": "Error with a code, see ErrorCode for the status of each code",
+                "content": { "application/json": { "schema": response_schema::<ApiError>() } },
+            }),
+        );
+        let mut operation = json!({
+            "operationId": self.id,
+            "summary": self.summary,
+            "responses": responses,
+        });
+        if !self.parameters.is_empty() {
+            operation["parameters"] = json!(self.parameters);
+        }
+        if let Some(schema) = &self.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema } },
+            });
+        }
+        if let Some(scheme) = self.security {
+            operation["security"] = json!([{ scheme: [] }]);
+        }
+        operation
+    }
+}
+
+/// OpenAPI 3.0 document of the endpoints
+pub fn document(endpoints: &[Endpoint]) -> Value {
+    let mut paths = Map::new();
+    for endpoint in endpoints {
+        let item = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        item[endpoint.method.as_str().to_lowercase()] = endpoint.operation();
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "token": { "type": "apiKey", "in": "header", "name": "Authorization" },
+                "admin": { "type": "apiKey", "in": "header", "name": "Authorization" },
+            },
+        },
+    })
+}
+
+pub fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok().json(document(&endpoints()))
+}
+
+/// Schema of a T in a request, fields that T can do without are optional
+pub fn request_schema<T: DeserializeOwned>() -> Value {
+    let mut reflection = Reflection::default();
+    reflect::<T>(&mut reflection);
+    for field in reflection.fields.clone() {
+        reflection.omit = Some(field);
+        if T::deserialize(Reflector::new(&mut reflection, &mut Value::Null)).is_ok() {
+            reflection.optional.insert(field);
+        }
+    }
+    reflection.omit = None;
+    reflect::<T>(&mut reflection)
+}
+
+/// Schema of a T in a response, which always has all fields
+pub fn response_schema<T: DeserializeOwned>() -> Value {
+    reflect::<T>(&mut Reflection::default())
+}
+
+fn reflect<T: DeserializeOwned>(reflection: &mut Reflection) -> Value {
+    let mut schema = Value::Null;
+    if let Err(err) = T::deserialize(Reflector::new(reflection, &mut schema)) {
+        panic!("Can not describe {}: {}", type_name::<T>(), err);
+    }
+    schema
+}
+
+// struct name and field name
+type Field = (&'static str, &'static str);
+
+#[derive(Default)]
+struct Reflection {
+    // fields of all structs that were visited
+    fields: Vec<Field>,
+    // the field that is left out to see whether it is optional
+    omit: Option<Field>,
+    optional: HashSet<Field>,
+}
+
+/// Deserializer that follows the Deserialize implementation of a type and
+/// writes the schema of what it asks for. Every sequence has a single element
+/// and the values are placeholders.
+struct Reflector<'a> {
+    reflection: &'a mut Reflection,
+    schema: &'a mut Value,
+}
+
+impl<'a> Reflector<'a> {
+    fn new(reflection: &'a mut Reflection, schema: &'a mut Value) -> Self {
+        Reflector { reflection, schema }
+    }
+}
+
+#[derive(Debug)]
+struct ReflectionError(String);
+
+impl fmt::Display for ReflectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ReflectionError {}
+
+impl de::Error for ReflectionError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ReflectionError(msg.to_string())
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Reflector<'a> {
+    type Error = ReflectionError;
+
+    // untagged and internally tagged enums, flattened fields and maps
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom(
+            "only structs, unit enums, sequences and primitives are supported",
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        *self.schema = json!({ "type": "boolean" });
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        *self.schema = json!({ "type": "integer" });
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        *self.schema = json!({ "type": "integer", "minimum": 0 });
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        *self.schema = json!({ "type": "number" });
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        *self.schema = json!({ "type": "string" });
+        visitor.visit_string(String::new())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let value = visitor.visit_some(Reflector::new(self.reflection, self.schema))?;
+        self.schema["nullable"] = json!(true);
+        Ok(value)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut items = Vec::new();
+        let value = visitor.visit_seq(Elements::new(self.reflection, &mut items, 1))?;
+        *self.schema = json!({ "type": "array", "items": items[0] });
+        Ok(value)
+    }
+
+    // arrays and tuples, whose elements must have the same type
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let mut items = Vec::new();
+        let value = visitor.visit_seq(Elements::new(self.reflection, &mut items, len))?;
+        if items.iter().any(|item| *item != items[0]) {
+            return Err(de::Error::custom(
+                "tuples of different types are not supported",
+            ));
+        }
+        *self.schema = json!({
+            "type": "array",
+            "items": items[0],
+            "minItems": len,
+            "maxItems": len,
+        });
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let mut properties = Map::new();
+        let value = visitor.visit_map(Fields {
+            reflection: &mut *self.reflection,
+            name,
+            fields: fields.iter(),
+            current: "",
+            properties: &mut properties,
+        })?;
+        let required: Vec<&str> = fields
+            .iter()
+            .filter(|field| !self.reflection.optional.contains(&(name, **field)))
+            .cloned()
+            .collect();
+        *self.schema = json!({ "type": "object", "properties": properties });
+        if !required.is_empty() {
+            self.schema["required"] = json!(required);
+        }
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.schema = json!({ "type": "string", "enum": variants });
+        visitor.visit_enum(UnitVariant(variants[0]))
+    }
+
+    forward_to_deserialize_any! {
+        char str bytes byte_buf unit unit_struct map identifier ignored_any
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_tuple(len, visitor)
+    }
+}
+
+struct Elements<'a> {
+    reflection: &'a mut Reflection,
+    items: &'a mut Vec<Value>,
+    len: usize,
+}
+
+impl<'a> Elements<'a> {
+    fn new(reflection: &'a mut Reflection, items: &'a mut Vec<Value>, len: usize) -> Self {
+        Elements {
+            reflection,
+            items,
+            len,
+        }
+    }
+}
+
+impl<'de, 'a> SeqAccess<'de> for Elements<'a> {
+    type Error = ReflectionError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        if self.items.len() == self.len {
+            return Ok(None);
+        }
+        let mut schema = Value::Null;
+        let value = seed.deserialize(Reflector::new(self.reflection, &mut schema))?;
+        self.items.push(schema);
+        Ok(Some(value))
+    }
+}
+
+struct Fields<'a> {
+    reflection: &'a mut Reflection,
+    name: &'static str,
+    fields: std::slice::Iter<'static, &'static str>,
+    current: &'static str,
+    properties: &'a mut Map<String, Value>,
+}
+
+impl<'de, 'a> MapAccess<'de> for Fields<'a> {
+    type Error = ReflectionError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        for field in &mut self.fields {
+            let field = (self.name, *field);
+            if !self.reflection.fields.contains(&field) {
+                self.reflection.fields.push(field);
+            }
+            if self.reflection.omit == Some(field) {
+                continue;
+            }
+            self.current = field.1;
+            return seed.deserialize(field.1.into_deserializer()).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let mut schema = Value::Null;
+        let value = seed.deserialize(Reflector::new(self.reflection, &mut schema))?;
+        self.properties.insert(String::from(self.current), schema);
+        Ok(value)
+    }
+}
+
+// the first variant of an enum, only unit variants are supported
+struct UnitVariant(&'static str);
+
+impl<'de> EnumAccess<'de> for UnitVariant {
+    type Error = ReflectionError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), Self::Error> {
+        let variant = seed.deserialize(self.0.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for UnitVariant {
+    type Error = ReflectionError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        _seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        Err(de::Error::custom("only unit variants are supported"))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only unit variants are supported"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only unit variants are supported"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::jobs::JobInfo;
+    use crate::server::routing::RouteRequest;
+
+    #[test]
+    fn schemas() {
+        let request = request_schema::<RouteRequest>();
+        assert_eq!(json!(["waypoints", "alpha"]), request["required"]);
+        let alpha = &request["properties"]["alpha"];
+        assert_eq!(json!("number"), alpha["items"]["type"]);
+        assert_eq!(json!(4), alpha["minItems"]);
+        let coordinate = &request["properties"]["waypoints"]["items"];
+        assert_eq!(json!(["lat", "lng"]), coordinate["required"]);
+        assert_eq!(json!(true), request["properties"]["graph"]["nullable"]);
+
+        // responses always contain every field
+        let response = response_schema::<JobInfo>();
+        assert_eq!(
+            json!(["id", "route_id", "status", "progress", "error"]),
+            response["required"]
+        );
+        assert_eq!(
+            json!("finished"),
+            response["properties"]["status"]["enum"][2]
+        );
+        assert_eq!(json!(true), response["properties"]["error"]["nullable"]);
+        assert_eq!(
+            json!("INVALID_REQUEST"),
+            response["properties"]["error"]["properties"]["code"]["enum"][0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Can not describe")]
+    fn maps() {
+        response_schema::<std::collections::HashMap<String, usize>>();
+    }
+}
@@ -1,211 +1,451 @@
+use std::thread;
+
 use actix_web::{web, HttpRequest, HttpResponse};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::helpers::{Coordinate, Preference};
+use crate::graph::avoid::Avoid;
+use crate::graph::constrained::{Budgets, ConstrainedResult};
+use crate::graph::customize::EdgeUpdate;
+use crate::graph::learning::LearningObserver;
+use crate::graph::path::Path as RoutePath;
+use crate::helpers::{BoundingBox, Coordinate, Preference};
+use crate::logging;
+use crate::metrics::metrics;
+use crate::user::UserState;
 
+use super::error::{ApiError, ErrorCode};
+use super::validate::{check_alpha, check_alphas, check_coordinates, check_waypoints};
 use super::AppState;
 use crate::config::get_config;
 use actix_web::web::Path;
 
+#[derive(Deserialize)]
+pub struct RouteRequest {
+    pub waypoints: Vec<Coordinate>,
+    pub alpha: Preference,
+    #[serde(default)]
+    pub optimize_order: bool,
+    #[serde(default)]
+    pub avoid: Avoid,
+    // name of the graph to route on, selected by the waypoints if missing
+    #[serde(default)]
+    pub graph: Option<String>,
+}
+
+// the fields of RouteRequest are repeated, flattened fields would be missing
+// from the OpenAPI document
 #[derive(Deserialize)]
 pub struct FspRequest {
+    // 0 for a new route, else the id of the stored route to replace
     id: usize,
     waypoints: Vec<Coordinate>,
     alpha: Preference,
+    #[serde(default)]
+    optimize_order: bool,
+    #[serde(default)]
+    avoid: Avoid,
+    #[serde(default)]
+    graph: Option<String>,
+}
+
+impl FspRequest {
+    fn into_route(self) -> (usize, RouteRequest) {
+        let route = RouteRequest {
+            waypoints: self.waypoints,
+            alpha: self.alpha,
+            optimize_order: self.optimize_order,
+            avoid: self.avoid,
+            graph: self.graph,
+        };
+        (self.id, route)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PartitionRequest {
+    source: Coordinate,
+    target: Coordinate,
+    #[serde(default)]
+    graph: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ConstrainedRequest {
+    source: Coordinate,
+    target: Coordinate,
+    alpha: Preference,
+    budgets: Budgets,
+    #[serde(default)]
+    graph: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ConstrainedResponse {
+    path: Option<RoutePath>,
+    // tags of the dimensions whose budgets can not be kept
+    infeasible: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EdgeUpdateResponse {
+    edges: usize,
+    shortcuts: usize,
+}
+
+#[derive(Deserialize)]
+pub struct SensitivityRequest {
+    waypoints: Vec<Coordinate>,
+    alpha: Preference,
+    #[serde(default)]
+    graph: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ClosestQuery {
+    lat: f64,
+    lng: f64,
+    graph: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct GraphQuery {
+    graph: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct GraphInfo {
+    name: String,
+    bounding_box: BoundingBox,
+}
+
+pub fn get_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics().render())
 }
 
 pub fn get_cost_tags() -> HttpResponse {
     HttpResponse::Ok().json(get_config().edge_cost_tags())
 }
 
-pub fn find_closest(query: web::Query<Coordinate>, state: web::Data<AppState>) -> HttpResponse {
-    let graph = &state.graph;
-    let coordinate = query.into_inner();
+pub fn get_graphs(state: web::Data<AppState>) -> HttpResponse {
+    let graphs: Vec<GraphInfo> = state
+        .graphs()
+        .iter()
+        .map(|graph| GraphInfo {
+            name: String::from(graph.name()),
+            bounding_box: *graph.bounding_box(),
+        })
+        .collect();
+    HttpResponse::Ok().json(graphs)
+}
+
+pub fn find_closest(
+    query: web::Query<ClosestQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let coordinate = Coordinate {
+        lat: query.lat,
+        lng: query.lng,
+    };
+    let graph = state
+        .graph(query.graph.as_deref(), &[coordinate])
+        .ok_or_else(ApiError::unknown_graph)?;
 
     let location = &graph.find_closest_node(&coordinate).location;
-    HttpResponse::Ok().json(location)
+    Ok(HttpResponse::Ok().json(location))
 }
 
 pub fn fsp(
     req: HttpRequest,
     body: web::Json<FspRequest>,
     state: web::Data<AppState>,
-) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    let data = body.into_inner();
-                    let id = data.id;
-                    let path = state
-                        .graph
-                        .find_shortest_path_alt(id, data.waypoints, data.alpha);
-                    if id != 0 {
-                        user.update_route(path.as_ref());
-                    }
-                    HttpResponse::Ok().json(path)
-                }
-            }
-        }
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    let (id, route) = body.into_inner().into_route();
+    let path = find_route(&state, id, route)?;
+    if id != 0 {
+        user.update_route(Some(&path));
     }
+    Ok(HttpResponse::Ok().json(path))
 }
 
-pub fn get_preference(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => HttpResponse::Ok().json(&user.alphas),
-            }
-        }
-    }
+pub fn get_preference(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    Ok(HttpResponse::Ok().json(&user.alphas))
 }
 
 pub fn set_preference(
     req: HttpRequest,
     body: web::Json<Vec<Preference>>,
     state: web::Data<AppState>,
-) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    let new_alphas = body.into_inner();
-                    user.alphas = new_alphas;
-                    HttpResponse::Ok().finish()
-                }
-            }
-        }
-    }
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    user.alphas = check_alphas(body.into_inner(), get_config().normalize_preferences())?;
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub fn new_preference(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    user.add_pref();
-                    HttpResponse::Ok().json(&user.alphas)
-                }
-            }
-        }
-    }
+pub fn new_preference(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    user.add_pref();
+    Ok(HttpResponse::Ok().json(&user.alphas))
 }
 
 pub fn find_preference(
     req: HttpRequest,
     state: web::Data<AppState>,
     body: web::Json<FspRequest>,
-) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    let body = body.into_inner();
-                    let id = body.id;
-                    let graph = &state.graph;
-                    let mut route = graph
-                        .find_shortest_path_alt(id, body.waypoints, body.alpha)
-                        .unwrap();
-
-                    graph.find_preference(&mut route);
-                    if id == 0 {
-                        user.add_route(&mut route);
-                    } else {
-                        user.update_route(Some(&route));
-                    }
-                    HttpResponse::Ok().json(&user.driven_routes)
-                }
-            }
-        }
+) -> Result<HttpResponse, ApiError> {
+    authorize(&state, &req)?;
+    let (id, route) = body.into_inner().into_route();
+    let mut route = find_route(&state, id, route)?;
+    // other requests of the user go on while the route is learned
+    learn_route(&state, &mut route, &mut ())?;
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    if id == 0 {
+        user.add_route(&mut route);
+    } else {
+        user.update_route(Some(&route));
     }
+    Ok(HttpResponse::Ok().json(&user.driven_routes))
 }
 
-pub fn get_routes(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let users = state.users.lock().unwrap();
-            let user_state = users.iter().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    let routes = &user.driven_routes;
-                    HttpResponse::Ok().json(routes)
-                }
-            }
-        }
-    }
+pub fn find_partition(
+    body: web::Json<PartitionRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let PartitionRequest {
+        source,
+        target,
+        graph,
+    } = body.into_inner();
+    let graph = state
+        .graph(graph.as_deref(), &[source, target])
+        .ok_or_else(ApiError::unknown_graph)?;
+    check_coordinates(&graph, &[source, target])?;
+    let source = graph.find_closest_node(&source).id;
+    let target = graph.find_closest_node(&target).id;
+
+    let regions = graph
+        .find_preference_partition(source, target)
+        .ok_or_else(ApiError::no_route)?;
+    Ok(HttpResponse::Ok().json(regions))
+}
+
+pub fn constrained_fsp(
+    body: web::Json<ConstrainedRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let ConstrainedRequest {
+        source,
+        target,
+        alpha,
+        budgets,
+        graph,
+    } = body.into_inner();
+    let alpha = check_alpha(alpha, get_config().normalize_preferences())?;
+    let graph = state
+        .graph(graph.as_deref(), &[source, target])
+        .ok_or_else(ApiError::unknown_graph)?;
+    check_coordinates(&graph, &[source, target])?;
+    let source = graph.find_closest_node(&source).id;
+    let target = graph.find_closest_node(&target).id;
+
+    let response = match graph.find_constrained_path(source, target, alpha, budgets) {
+        ConstrainedResult::Found(path) => ConstrainedResponse {
+            path: Some(*path),
+            infeasible: Vec::new(),
+        },
+        ConstrainedResult::Infeasible(dims) => ConstrainedResponse {
+            path: None,
+            infeasible: dims
+                .iter()
+                .map(|dim| get_config().edge_cost_tags()[*dim].clone())
+                .collect(),
+        },
+        ConstrainedResult::NoRoute => return Err(ApiError::no_route()),
+    };
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub fn update_edge_costs(
+    req: HttpRequest,
+    query: web::Query<GraphQuery>,
+    body: web::Json<Vec<EdgeUpdate>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    authorize_admin(&state, &req)?;
+    let updates = body.into_inner();
+    let shortcuts = state.update_graph(query.graph.as_deref(), &updates)?;
+    Ok(HttpResponse::Ok().json(EdgeUpdateResponse {
+        edges: updates.len(),
+        shortcuts,
+    }))
+}
+
+pub fn reload_graph(
+    req: HttpRequest,
+    name: Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    authorize_admin(&state, &req)?;
+    let index = state.start_reload(&name)?;
+    let state = state.clone();
+    let request_id = logging::request_id();
+    thread::spawn(move || {
+        // the reload is logged as part of the request that started it
+        let _request = request_id.map(logging::enter_request);
+        state.reload_graph(index)
+    });
+    Ok(HttpResponse::Accepted().finish())
+}
+
+pub fn find_sensitivity(
+    body: web::Json<SensitivityRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let SensitivityRequest {
+        waypoints,
+        alpha,
+        graph,
+    } = body.into_inner();
+    let alpha = check_alpha(alpha, get_config().normalize_preferences())?;
+    let graph = state
+        .graph(graph.as_deref(), &waypoints)
+        .ok_or_else(ApiError::unknown_graph)?;
+    check_waypoints(&graph, &waypoints)?;
+
+    let path = graph
+        .find_shortest_path_alt(0, waypoints, alpha)
+        .ok_or_else(ApiError::no_route)?;
+    // the path is only not optimal for the alpha because of rounding
+    let sensitivity = graph.find_sensitivity(&path, alpha).ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::LearningFailed,
+            "The route is not optimal for the alpha",
+        )
+    })?;
+    Ok(HttpResponse::Ok().json(sensitivity))
+}
+
+pub fn get_routes(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    Ok(HttpResponse::Ok().json(&user.driven_routes))
 }
 
 pub fn delete_route(
     req: HttpRequest,
     path: Path<usize>,
     state: web::Data<AppState>,
-) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    let id = path.into_inner();
-                    user.delete_route(id);
-                    HttpResponse::Ok().json(&user.driven_routes)
-                }
-            }
-        }
-    }
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    user.delete_route(path.into_inner());
+    Ok(HttpResponse::Ok().json(&user.driven_routes))
+}
+
+pub fn reset_data(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    user.reset();
+    Ok(HttpResponse::Ok().finish())
 }
 
-pub fn reset_data(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
-    match extract_token(&req) {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(token) => {
-            let mut users = state.users.lock().unwrap();
-            let user_state = users.iter_mut().find(|x| x.auth.token == token);
-            match user_state {
-                None => HttpResponse::Unauthorized().finish(),
-                Some(user) => {
-                    user.reset();
-                    HttpResponse::Ok().finish()
-                }
-            }
-        }
+/// Validates the request and finds the route on the selected graph, the id is
+/// stored in the path
+pub(super) fn find_route(
+    state: &AppState,
+    id: usize,
+    request: RouteRequest,
+) -> Result<RoutePath, ApiError> {
+    let alpha = check_alpha(request.alpha, get_config().normalize_preferences())?;
+    let graph = state
+        .graph(request.graph.as_deref(), &request.waypoints)
+        .ok_or_else(ApiError::unknown_graph)?;
+    check_waypoints(&graph, &request.waypoints)?;
+    let path = if request.optimize_order {
+        graph.find_shortest_path_optimized_alt(id, request.waypoints, alpha, &request.avoid)
+    } else {
+        graph.find_shortest_path_avoiding_alt(id, request.waypoints, alpha, &request.avoid)
+    };
+    path.ok_or_else(ApiError::no_route)
+}
+
+/// Finds the preferences of the route on the graph it belongs to and stores
+/// them as its algo split. A search the observer cancels leaves the route
+/// unchanged.
+pub(super) fn learn_route(
+    state: &AppState,
+    route: &mut RoutePath,
+    observer: &mut dyn LearningObserver,
+) -> Result<(), ApiError> {
+    if route.stale {
+        return Err(ApiError::new(
+            ErrorCode::RouteStale,
+            format!(
+                "Route {} is not part of the current graph {}",
+                route.id, route.graph
+            ),
+        ));
+    }
+    let graph = state
+        .graph(Some(&route.graph), &[])
+        .ok_or_else(ApiError::unknown_graph)?;
+    if !graph.find_preference_observed(route, observer) {
+        return Ok(());
+    }
+    if route.algo_split.is_none() {
+        return Err(ApiError::new(
+            ErrorCode::LearningFailed,
+            "No combination of preferences explains the route",
+        ));
     }
+    Ok(())
+}
+
+/// User with the token of the request
+pub(super) fn find_user<'a>(
+    users: &'a mut [UserState],
+    req: &HttpRequest,
+) -> Result<&'a mut UserState, ApiError> {
+    let token = extract_token(req)?;
+    users
+        .iter_mut()
+        .find(|x| x.auth.token == token)
+        .ok_or_else(ApiError::unauthorized)
 }
 
-fn extract_token(req: &HttpRequest) -> Option<&str> {
-    let auth_header = req.headers().get("Authorization");
-    match auth_header {
-        None => None,
-        Some(value) => {
-            let value = value.to_str().unwrap();
-            if value.is_empty() {
-                return None;
-            }
-            Some(value)
-        }
+/// Checks that the request has the token of any user
+pub(super) fn authorize(state: &AppState, req: &HttpRequest) -> Result<(), ApiError> {
+    find_user(&mut state.users.lock()?, req).map(|_| ())
+}
+
+/// Checks that the request has the admin token. Administrative requests are
+/// rejected if none is configured.
+pub(super) fn authorize_admin(state: &AppState, req: &HttpRequest) -> Result<(), ApiError> {
+    match &state.admin_token {
+        Some(admin_token) if extract_token(req).ok() == Some(admin_token) => Ok(()),
+        _ => Err(ApiError::not_admin()),
     }
 }
+
+fn extract_token(req: &HttpRequest) -> Result<&str, ApiError> {
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .ok_or_else(ApiError::unauthorized)
+}
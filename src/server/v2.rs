@@ -0,0 +1,197 @@
+use actix_web::web::Path;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::get_config;
+use crate::graph::avoid::Avoid;
+use crate::helpers::{Coordinate, Preference};
+
+use super::error::ApiError;
+use super::routing::{find_route, find_user, RouteRequest};
+use super::websocket::stream_json;
+use super::AppState;
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    // browsers can not set the Authorization header of WebSocket requests
+    #[serde(default)]
+    token: Option<String>,
+}
+
+/// Changes of a stored route, missing fields keep their value
+#[derive(Deserialize)]
+pub struct RoutePatch {
+    #[serde(default)]
+    waypoints: Option<Vec<Coordinate>>,
+    #[serde(default)]
+    alpha: Option<Preference>,
+    #[serde(default)]
+    avoid: Option<Avoid>,
+}
+
+pub fn list_routes(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    Ok(HttpResponse::Ok().json(&user.driven_routes))
+}
+
+pub fn create_route(
+    req: HttpRequest,
+    body: web::Json<RouteRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    let mut route = find_route(&state, 0, body.into_inner())?;
+    user.add_route(&mut route);
+    Ok(HttpResponse::Created()
+        .header("Location", format!("/v2/routes/{}", route.id))
+        .json(route))
+}
+
+pub fn get_route(
+    req: HttpRequest,
+    id: Path<usize>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    let route = user
+        .route(*id)
+        .ok_or_else(|| ApiError::route_not_found(*id))?;
+    Ok(HttpResponse::Ok().json(route))
+}
+
+/// Finds the route again for new waypoints and preference, which drops
+/// learned preferences
+pub fn replace_route(
+    req: HttpRequest,
+    id: Path<usize>,
+    body: web::Json<RouteRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    let stored = user
+        .route_mut(*id)
+        .ok_or_else(|| ApiError::route_not_found(*id))?;
+    *stored = find_route(&state, *id, body.into_inner())?;
+    Ok(HttpResponse::Ok().json(stored))
+}
+
+/// Like replace_route, but the waypoints, the preference of the first leg, the
+/// avoided parts and the graph of the stored route are kept unless the patch
+/// changes them. Stale routes only keep the avoided polygons, their ids belong
+/// to a graph that is gone.
+pub fn update_route(
+    req: HttpRequest,
+    id: Path<usize>,
+    body: web::Json<RoutePatch>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    let stored = user
+        .route_mut(*id)
+        .ok_or_else(|| ApiError::route_not_found(*id))?;
+    let patch = body.into_inner();
+    let request = RouteRequest {
+        waypoints: patch.waypoints.unwrap_or_else(|| stored.waypoints.clone()),
+        alpha: patch
+            .alpha
+            .or_else(|| stored.user_split.alphas.first().copied())
+            .unwrap_or_else(|| get_config().initial_pref()),
+        optimize_order: false,
+        avoid: patch.avoid.unwrap_or_else(|| {
+            if stored.stale {
+                stored.avoid.polygons_only()
+            } else {
+                stored.avoid.clone()
+            }
+        }),
+        graph: Some(stored.graph.clone()),
+    };
+    *stored = find_route(&state, *id, request)?;
+    Ok(HttpResponse::Ok().json(stored))
+}
+
+pub fn delete_route(
+    req: HttpRequest,
+    id: Path<usize>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    user.delete_route(*id)
+        .ok_or_else(|| ApiError::route_not_found(*id))?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Queues a job that finds the preferences the route was driven with and
+/// stores them in the route
+pub fn learn(
+    req: HttpRequest,
+    id: Path<usize>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let mut users = state.users.lock()?;
+    let user = find_user(&mut users, &req)?;
+    let route = user
+        .route(*id)
+        .ok_or_else(|| ApiError::route_not_found(*id))?
+        .clone();
+    let job = state.jobs.submit(&user.auth.username, route)?;
+    Ok(HttpResponse::Accepted()
+        .header("Location", format!("/v2/jobs/{}", job.id))
+        .json(job))
+}
+
+pub fn list_jobs(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let username = username(&state, &req)?;
+    Ok(HttpResponse::Ok().json(state.jobs.jobs(&username)?))
+}
+
+pub fn get_job(
+    req: HttpRequest,
+    id: Path<usize>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let username = username(&state, &req)?;
+    Ok(HttpResponse::Ok().json(state.jobs.job(&username, *id)?))
+}
+
+/// Cancels the job, it keeps its state if it is done already
+pub fn cancel_job(
+    req: HttpRequest,
+    id: Path<usize>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let username = username(&state, &req)?;
+    Ok(HttpResponse::Ok().json(state.jobs.cancel(&username, *id)?))
+}
+
+/// Streams the events of the job over a WebSocket until the job is done
+pub fn job_events(
+    req: HttpRequest,
+    id: Path<usize>,
+    query: web::Query<EventsQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let username = match &query.token {
+        Some(token) => state
+            .users
+            .lock()?
+            .iter()
+            .find(|user| user.auth.token == *token)
+            .map(|user| user.auth.username.clone())
+            .ok_or_else(ApiError::unauthorized)?,
+        None => username(&state, &req)?,
+    };
+    let events = state.jobs.subscribe(&username, *id)?;
+    stream_json(&req, events)
+}
+
+fn username(state: &AppState, req: &HttpRequest) -> Result<String, ApiError> {
+    let mut users = state.users.lock()?;
+    Ok(find_user(&mut users, req)?.auth.username.clone())
+}
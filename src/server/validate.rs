@@ -0,0 +1,139 @@
+use crate::config::get_config;
+use crate::graph::Graph;
+use crate::helpers::{Coordinate, Preference};
+
+use super::error::{ApiError, ErrorCode};
+
+/// Checks that all components of the alpha are finite and non-negative and
+/// that one of them is positive. Returns the alpha scaled to a sum of 1 if
+/// normalize is set, else unchanged.
+pub fn check_alpha(alpha: Preference, normalize: bool) -> Result<Preference, ApiError> {
+    let tags = get_config().edge_cost_tags();
+    for (index, value) in alpha.iter().enumerate() {
+        if !value.is_finite() || *value < 0.0 {
+            return Err(invalid_preference(format!(
+                "The {} component of the preference {:?} must be a non-negative number",
+                tags[index], alpha
+            )));
+        }
+    }
+    let sum: f64 = alpha.iter().sum();
+    if sum <= 0.0 {
+        return Err(invalid_preference(format!(
+            "The preference {:?} needs a positive component",
+            alpha
+        )));
+    }
+    if !normalize {
+        return Ok(alpha);
+    }
+    let mut normalized = alpha;
+    for value in normalized.iter_mut() {
+        *value /= sum;
+    }
+    Ok(normalized)
+}
+
+/// Checks all alphas of a user, of which there has to be at least one
+pub fn check_alphas(alphas: Vec<Preference>, normalize: bool) -> Result<Vec<Preference>, ApiError> {
+    if alphas.is_empty() {
+        return Err(invalid_preference(String::from(
+            "At least one preference is needed",
+        )));
+    }
+    alphas
+        .into_iter()
+        .map(|alpha| check_alpha(alpha, normalize))
+        .collect()
+}
+
+/// Checks that a route has a start and an end and that all waypoints lie in
+/// the bounding box of the graph
+pub fn check_waypoints(graph: &Graph, waypoints: &[Coordinate]) -> Result<(), ApiError> {
+    if waypoints.len() < 2 {
+        return Err(ApiError::new(
+            ErrorCode::InvalidWaypoints,
+            format!(
+                "A route needs at least 2 waypoints, not {}",
+                waypoints.len()
+            ),
+        ));
+    }
+    check_coordinates(graph, waypoints)
+}
+
+/// Checks that the coordinates lie in the bounding box of the graph
+pub fn check_coordinates(graph: &Graph, coordinates: &[Coordinate]) -> Result<(), ApiError> {
+    let bounding_box = graph.bounding_box();
+    match coordinates
+        .iter()
+        .position(|coordinate| !bounding_box.contains(coordinate))
+    {
+        Some(index) => Err(ApiError::new(
+            ErrorCode::InvalidWaypoints,
+            format!(
+                "Waypoint {} at ({}, {}) is outside of graph {}, which covers ({}, {}) to ({}, {})",
+                index,
+                coordinates[index].lat,
+                coordinates[index].lng,
+                graph.name(),
+                bounding_box.min.lat,
+                bounding_box.min.lng,
+                bounding_box.max.lat,
+                bounding_box.max.lng
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn invalid_preference(message: String) -> ApiError {
+    ApiError::new(ErrorCode::InvalidPreference, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::generator::generate_grid_graph;
+
+    #[test]
+    fn alphas() {
+        assert_eq!(
+            Ok([0.5, 0.0, 0.5, 0.0]),
+            check_alpha([2.0, 0.0, 2.0, 0.0], true)
+        );
+        assert_eq!(
+            Ok([2.0, 0.0, 2.0, 0.0]),
+            check_alpha([2.0, 0.0, 2.0, 0.0], false)
+        );
+        for alpha in &[
+            [0.0; 4],
+            [1.0, -0.5, 0.5, 0.0],
+            [f64::NAN, 0.0, 1.0, 0.0],
+            [f64::INFINITY, 0.0, 0.0, 0.0],
+        ] {
+            let error = check_alpha(*alpha, true).unwrap_err();
+            assert_eq!(ErrorCode::InvalidPreference, error.code);
+        }
+        assert!(check_alphas(Vec::new(), true).is_err());
+        assert!(check_alphas(vec![[1.0, 0.0, 0.0, 0.0], [0.0; 4]], true).is_err());
+    }
+
+    #[test]
+    fn waypoints() {
+        let graph = generate_grid_graph(3, 3);
+        let corners = [graph.bounding_box().min, graph.bounding_box().max];
+        assert_eq!(Ok(()), check_waypoints(&graph, &corners));
+
+        let error = check_waypoints(&graph, &corners[..1]).unwrap_err();
+        assert_eq!(ErrorCode::InvalidWaypoints, error.code);
+
+        let outside = Coordinate {
+            lat: corners[1].lat + 1.0,
+            lng: corners[1].lng,
+        };
+        let error = check_waypoints(&graph, &[corners[0], outside]).unwrap_err();
+        assert_eq!(ErrorCode::InvalidWaypoints, error.code);
+        assert!(error.message.starts_with("Waypoint 1 "));
+    }
+}
@@ -0,0 +1,59 @@
+use actix_http::ws::{self, CloseCode, CloseReason, OpCode, Parser};
+use actix_web::{HttpRequest, HttpResponse};
+use bytes::{Bytes, BytesMut};
+use futures::{stream, Stream};
+use serde::Serialize;
+
+use super::error::{ApiError, ErrorCode};
+
+/// Answers the WebSocket handshake of the request and sends every item of the
+/// stream as a JSON text frame. The connection is closed when the stream ends.
+/// Frames of the client are not read.
+pub fn stream_json<S>(req: &HttpRequest, items: S) -> Result<HttpResponse, ApiError>
+where
+    S: Stream<Error = ()> + 'static,
+    S::Item: Serialize,
+{
+    let mut response = ws::handshake(req.head()).map_err(|err| {
+        ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("Expected a WebSocket upgrade: {}", err),
+        )
+    })?;
+    let frames = items
+        .map(|item| {
+            let text = serde_json::to_string(&item).unwrap_or_default();
+            text_frame(&text)
+        })
+        .chain(stream::once(Ok(close_frame())))
+        .map_err(|()| ApiError::new(ErrorCode::Internal, "Event stream failed"));
+    Ok(response.streaming(frames))
+}
+
+fn text_frame(text: &str) -> Bytes {
+    let mut buffer = BytesMut::new();
+    Parser::write_message(&mut buffer, Bytes::from(text), OpCode::Text, true, false);
+    buffer.freeze()
+}
+
+fn close_frame() -> Bytes {
+    let mut buffer = BytesMut::new();
+    Parser::write_close(
+        &mut buffer,
+        Some(CloseReason::from(CloseCode::Normal)),
+        false,
+    );
+    buffer.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        // unmasked server frames: FIN and opcode, payload length, payload
+        assert_eq!(&b"\x81\x02{}"[..], &text_frame("{}")[..]);
+        assert_eq!(&b"\x88\x02\x03\xe8"[..], &close_frame()[..]);
+    }
+}
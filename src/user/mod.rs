@@ -7,6 +7,7 @@ use sha3::{Digest, Sha3_512};
 #[derive(Deserialize, Serialize)]
 pub struct UserState {
     pub auth: UserAuth,
+    #[serde(with = "crate::graph::path::stored")]
     pub driven_routes: Vec<Path>,
     pub counter: usize,
     pub alphas: Vec<Preference>,
@@ -40,11 +41,18 @@ impl UserState {
         }
     }
 
-    pub fn delete_route(&mut self, id: usize) {
-        let index = self.driven_routes.iter().position(|path| path.id == id);
-        if let Some(index) = index {
-            self.driven_routes.remove(index);
-        }
+    pub fn route(&self, id: usize) -> Option<&Path> {
+        self.driven_routes.iter().find(|path| path.id == id)
+    }
+
+    pub fn route_mut(&mut self, id: usize) -> Option<&mut Path> {
+        self.driven_routes.iter_mut().find(|path| path.id == id)
+    }
+
+    /// Removes the route, None if the user has no route with the id
+    pub fn delete_route(&mut self, id: usize) -> Option<Path> {
+        let index = self.driven_routes.iter().position(|path| path.id == id)?;
+        Some(self.driven_routes.remove(index))
     }
 
     pub fn add_pref(&mut self) {
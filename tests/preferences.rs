@@ -0,0 +1,27 @@
+use preference_routing::parse_graph_file;
+
+#[test]
+fn preference_partition() {
+    let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+    let regions = graph.find_preference_partition(2, 10).unwrap();
+    assert_eq!(2, regions.len());
+    for region in regions {
+        // the route of a region is optimal at its center
+        let path = graph
+            .find_shortest_path(0, vec![2, 10], region.center)
+            .unwrap();
+        assert_eq!(region.path.edges, path.edges);
+    }
+}
+
+#[test]
+fn sensitivity() {
+    let graph = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+    let alpha = [1.0, 0.0, 0.0, 0.0];
+    let path = graph.find_shortest_path(0, vec![2, 10], alpha).unwrap();
+    let sensitivity = graph.find_sensitivity(&path, alpha).unwrap();
+    assert!((sensitivity.stability_radius.unwrap() - 0.5).abs() < 1e-6);
+    assert!(graph
+        .find_sensitivity(&path, [0.0, 0.0, 1.0, 0.0])
+        .is_none());
+}
@@ -0,0 +1,78 @@
+use preference_routing::graph::generator::generate_grid_graph;
+use preference_routing::graph::path::index_locations;
+use preference_routing::{parse_graph_file, Avoid, EdgeUpdate, Graph};
+
+const ALPHA: [f64; 4] = [1.0, 0.0, 0.0, 0.0];
+
+fn contracted_graph() -> Graph {
+    parse_graph_file("./src/test_graphs/concTestGraph").unwrap()
+}
+
+#[test]
+fn shortest_path() {
+    let graph = contracted_graph();
+    let path = graph.find_shortest_path(0, vec![2, 10], ALPHA).unwrap();
+    assert_eq!(vec![2, 4, 5, 7, 10], path.nodes);
+    assert_eq!(vec![4, 7, 9, 12], path.edges);
+    assert_eq!([4.0, 4.0, 4.0, 4.0], path.total_dimension_costs);
+    assert!(path.matches(&graph));
+
+    // the hierarchy and the original graph agree
+    let plain = parse_graph_file("./src/test_graphs/testGraph").unwrap();
+    let plain_path = plain.find_shortest_path(0, vec![2, 10], ALPHA).unwrap();
+    assert_eq!(path.total_dimension_costs, plain_path.total_dimension_costs);
+}
+
+#[test]
+fn avoiding_edges() {
+    let graph = contracted_graph();
+    let avoid = Avoid {
+        edges: vec![7],
+        ..Avoid::default()
+    };
+    let path = graph
+        .find_shortest_path_avoiding(0, vec![2, 10], ALPHA, &avoid)
+        .unwrap();
+    assert_eq!(vec![5, 9, 12], path.edges);
+}
+
+#[test]
+fn edge_updates() {
+    let mut graph = contracted_graph();
+    let update = EdgeUpdate {
+        edge: 7,
+        costs: [5.0, 5.0, 1.0, 1.0],
+    };
+    assert!(graph.update_edge_costs(&[update]).is_ok());
+    let path = graph.find_shortest_path(0, vec![2, 10], ALPHA).unwrap();
+    assert_eq!(vec![5, 9, 12], path.edges);
+
+    let shortcut = EdgeUpdate {
+        edge: 19,
+        costs: [1.0, 1.0, 1.0, 1.0],
+    };
+    assert!(graph.update_edge_costs(&[shortcut]).is_err());
+}
+
+#[test]
+fn restricted_turns() {
+    let mut graph = contracted_graph();
+    graph.add_turn(4, 7, None).unwrap();
+    let path = graph.find_shortest_path(0, vec![2, 10], ALPHA).unwrap();
+    assert_eq!(vec![5, 9, 12], path.edges);
+}
+
+#[test]
+fn generated_graphs() {
+    let graph = generate_grid_graph(6, 6);
+    assert!(graph.validate().is_empty());
+    let path = graph.find_shortest_path(0, vec![0, 35], ALPHA).unwrap();
+    assert_eq!(Some(&35), path.nodes.last());
+
+    // routes are matched to another graph by their coordinates
+    let wider = generate_grid_graph(7, 6);
+    let mut rematched = path.clone();
+    assert!(rematched.rematch(&wider, &index_locations(&wider)));
+    assert_eq!(path.total_dimension_costs, rematched.total_dimension_costs);
+    assert_ne!(graph.fingerprint(), wider.fingerprint());
+}